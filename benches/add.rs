@@ -0,0 +1,58 @@
+//! Compares `add`, which appends a single row, with `update`, which rewrites the whole ledger the way every `add`
+//! used to, on a large generated ledger. Run with `cargo bench`.
+use std::{fmt::Write as _, path::Path, process::Command, time::{Duration, Instant}};
+
+const ROWS: u32 = 50_000;
+const RUNS: u32 = 10;
+
+/// The header line and the values of the columns after `currency` come from a row written by expense-tracker itself,
+/// so the generated ledger always has the current columns and `add` appends to it instead of migrating it
+fn generate_ledger(file_path: &Path) {
+    time_command(file_path, &["add", "-k", "template", "-v", "1"]);
+    let template = std::fs::read_to_string(file_path).expect("could not read the template ledger");
+    let mut lines = template.lines();
+    let header = lines.next().expect("the template ledger has no header");
+    let row = lines.next().expect("the template ledger has no row");
+    let trailing: String = row.split(';').skip(7).map(|value| format!(";{value}")).collect();
+    let mut contents = format!("{header}\n");
+    for id in 1..=ROWS {
+        let day = id % 28 + 1;
+        let month = id % 12 + 1;
+        let year = 2015 + id % 10;
+        let _ = writeln!(
+            contents,
+            "{id};{}.{:02};expense number {id};{year}-{month:02}-{day:02};groceries;work,trip;EUR{trailing}",
+            id % 500, id % 100
+        );
+    }
+    std::fs::write(file_path, contents).expect("could not write the generated ledger");
+}
+
+fn time_command(file_path: &Path, args: &[&str]) -> Duration {
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_expense-tracker"))
+        .arg("--file")
+        .arg(file_path)
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .expect("could not run expense-tracker");
+    assert!(status.success(), "expense-tracker {args:?} failed");
+    started.elapsed()
+}
+
+fn main() {
+    let directory = tempfile::tempdir().expect("could not create a temporary directory");
+    let file_path = directory.path().join("expenses.csv");
+    generate_ledger(&file_path);
+    println!("Ledger with {ROWS} rows, mean of {RUNS} runs");
+
+    // On the date of the first row, so the rows of that date are read to look for duplicates.
+    // Every run has another amount, so none is found.
+    let add: Duration = (0..RUNS).map(|run| time_command(&file_path, &["add", "-k", "coffee", "-v", &format!("3.{run:02}"), "-d", "2016-02-02"])).sum();
+    let rewrite: Duration = (0..RUNS).map(|_| time_command(&file_path, &["update", "-i", "1", "-v", "3.50"])).sum();
+    let (add, rewrite) = (add / RUNS, rewrite / RUNS);
+    println!("add (append one row):   {add:>10.2?}");
+    println!("update (rewrite file):  {rewrite:>10.2?}");
+    println!("speedup:                {:>9.1}x", rewrite.as_secs_f64() / add.as_secs_f64());
+}
@@ -0,0 +1,93 @@
+use std::{fmt::Display, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::{atomic, currency::RateTable, money::Money, Expense, Kind};
+
+/// An account money is paid from or received into (a checking account, a credit card, cash...)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Account {
+    pub name: String,
+    /// Currency the balance is kept in
+    pub currency: String,
+    /// Balance before the first record of the ledger
+    pub opening_balance: Money,
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let opening_str = format!("{} {}", self.opening_balance, self.currency);
+        write!(f, "{:<12} | {:<8} | {}", self.name, self.currency, opening_str)
+    }
+}
+
+/// Accounts, stored in the ledger's `.accounts.csv` file.
+pub struct AccountTable {
+    pub accounts: Vec<Account>,
+}
+
+impl AccountTable {
+    pub fn load(file_path: &Path) -> Result<Self, csv::Error> {
+        Ok(AccountTable { accounts: atomic::load_table(file_path)? })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<(), csv::Error> {
+        atomic::save_table(file_path, &self.accounts)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Account> {
+        self.accounts.iter().find(|account| account.name == name)
+    }
+
+    /// Checks that the accounts of a record exist and fit its kind: transfers need two different accounts,
+    /// other records at most one
+    pub fn check(&self, record: &Expense) -> Result<(), String> {
+        match (record.kind, &record.account, &record.to_account) {
+            (Kind::Transfer, Some(from), Some(to)) if from == to => return Err("A transfer needs two different accounts".into()),
+            (Kind::Transfer, Some(_), Some(_)) => {}
+            (Kind::Transfer, ..) => return Err("A transfer needs both --account and --to-account".into()),
+            (_, _, Some(_)) => return Err("Only transfers have a --to-account".into()),
+            _ => {}
+        }
+        for name in record.account.iter().chain(&record.to_account) {
+            if self.get(name).is_none() {
+                return Err(format!("Unknown account '{name}' (add it with `account add --name {name}`)"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Change of the balance of `account` caused by a record, in the record's currency
+pub fn change(record: &Expense, account: &str) -> Option<Money> {
+    let is = |name: &Option<String>| name.as_deref() == Some(account);
+    match record.kind {
+        Kind::Expense if is(&record.account) => Some(-record.amount),
+        Kind::Income if is(&record.account) => Some(record.amount),
+        Kind::Transfer if is(&record.account) => Some(-record.amount),
+        Kind::Transfer if is(&record.to_account) => Some(record.amount),
+        _ => None,
+    }
+}
+
+/// One record of an account's history, with the balance right after it (in the account's currency)
+pub struct Movement<'a> {
+    pub record: &'a Expense,
+    pub change: Money,
+    pub balance: Money,
+}
+
+/// Every record of an account in date order, each converted to the account's currency with the rate of its date
+pub fn history<'a>(account: &Account, records: &'a [Expense], rates: &RateTable) -> Result<Vec<Movement<'a>>, String> {
+    let mut records: Vec<&Expense> = records.iter().collect();
+    records.sort_by_key(|record| (record.date, record.id));
+    let mut balance = account.opening_balance;
+    let mut movements = Vec::new();
+    for record in records {
+        let Some(change) = change(record, &account.name) else {
+            continue;
+        };
+        let change = rates.convert(change, &record.currency, &account.currency, record.date)?;
+        balance = balance.checked_add(change)?;
+        movements.push(Movement { record, change, balance });
+    }
+    Ok(movements)
+}
@@ -0,0 +1,82 @@
+use std::{fs::File, io, path::{Path, PathBuf}};
+use csv::{Writer, WriterBuilder};
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::NamedTempFile;
+
+/// Path of the backup kept of a file before it is replaced, e.g. expenses.csv.bak
+pub fn backup_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    file_path.with_file_name(name)
+}
+
+/// Copies the current version of a file to its backup, replacing the previous backup
+pub fn backup(file_path: &Path) -> io::Result<()> {
+    if file_path.exists() {
+        std::fs::copy(file_path, backup_path(file_path))?;
+    }
+    Ok(())
+}
+
+/// Writes a CSV file crash-safely: the rows go to a temporary file in the same directory, which is synced to disk and
+/// then renamed over the original. A crash or Ctrl-C at any point leaves either the old or the new file, never a mix.
+pub fn write_csv<F>(file_path: &Path, builder: &WriterBuilder, write: F) -> Result<(), csv::Error>
+where
+    F: FnOnce(&mut Writer<&mut File>) -> Result<(), csv::Error>,
+{
+    let directory = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp_file = NamedTempFile::new_in(directory)?;
+    {
+        let mut writer = builder.from_writer(temp_file.as_file_mut());
+        write(&mut writer)?;
+        writer.flush()?;
+    }
+    // Temporary files are created private, keep the permissions of the file being replaced instead
+    if let Ok(metadata) = std::fs::metadata(file_path) {
+        temp_file.as_file().set_permissions(metadata.permissions())?;
+    }
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(file_path).map_err(|e| e.error)?;
+    sync_directory(directory)?;
+    Ok(())
+}
+
+/// Reads one of the `;` separated sidecar tables kept next to the ledger (rates, budgets, accounts...).
+/// A missing file is an empty table, since nothing was added to it yet.
+pub fn load_table<T: DeserializeOwned>(file_path: &Path) -> Result<Vec<T>, csv::Error> {
+    if !file_path.exists() {
+        return Ok(Vec::new());
+    }
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b';')
+        .from_path(file_path)?
+        .deserialize()
+        .collect()
+}
+
+/// Writes a sidecar table crash-safely, with a header line
+pub fn save_table<T: Serialize>(file_path: &Path, rows: &[T]) -> Result<(), csv::Error> {
+    let mut builder = WriterBuilder::new();
+    builder.has_headers(true).delimiter(b';');
+    write_csv(file_path, &builder, |writer| {
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        Ok(())
+    })
+}
+
+/// The rename itself is only durable once the directory entry is synced (not supported on Windows)
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::{fmt::Display, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::{atomic, money::Money, report::{Section, SummaryRow}};
+
+/// Monthly spending limit in the base currency, for every expense (no category) or for a single category
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Budget {
+    pub category: Option<String>,
+    pub amount: Money,
+}
+
+impl Budget {
+    pub fn name(&self) -> &str {
+        self.category.as_deref().unwrap_or(OVERALL)
+    }
+}
+
+/// Name under which the budget of all expenses is shown
+const OVERALL: &str = "(overall)";
+
+impl Display for Budget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<12} | {} per month", self.name(), self.amount)
+    }
+}
+
+/// Budget definitions, stored in the ledger's `.budgets.csv` file.
+pub struct BudgetTable {
+    pub budgets: Vec<Budget>,
+}
+
+impl BudgetTable {
+    pub fn load(file_path: &Path) -> Result<Self, csv::Error> {
+        Ok(BudgetTable { budgets: atomic::load_table(file_path)? })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<(), csv::Error> {
+        atomic::save_table(file_path, &self.budgets)
+    }
+
+    /// Sets a budget, replacing the one of the same category if it already exists. The overall budget is listed first.
+    pub fn set(&mut self, budget: Budget) {
+        self.remove(budget.category.as_deref());
+        self.budgets.push(budget);
+        self.budgets.sort_by(|a, b| a.category.cmp(&b.category));
+    }
+
+    /// Whether there was a budget to remove
+    pub fn remove(&mut self, category: Option<&str>) -> bool {
+        let count = self.budgets.len();
+        self.budgets.retain(|budget| budget.category.as_deref() != category);
+        self.budgets.len() != count
+    }
+
+    /// Spending of every budget in a month, from the month's summary by category
+    pub fn status<'a>(&'a self, summary: &[SummaryRow]) -> Result<Vec<BudgetStatus<'a>>, String> {
+        self.budgets
+            .iter()
+            .map(|budget| {
+                // The summary has one row per category, and none for categories without spending
+                let spent = summary
+                    .iter()
+                    .find(|row| match &budget.category {
+                        None => row.section == Section::Total,
+                        Some(category) => row.section == Section::Category && row.key.as_ref() == Some(category),
+                    })
+                    .map_or(Money::default(), |row| row.base_amount);
+                Ok(BudgetStatus { budget, spent, remaining: budget.amount.checked_sub(spent)? })
+            })
+            .collect()
+    }
+}
+
+pub struct BudgetStatus<'a> {
+    pub budget: &'a Budget,
+    pub spent: Money,
+    /// Negative when over budget
+    pub remaining: Money,
+}
+
+impl BudgetStatus<'_> {
+    pub fn is_over(&self) -> bool {
+        self.spent > self.budget.amount
+    }
+
+    /// Percentage of the budget already spent, rounded to the nearest integer (budgets are always positive)
+    pub fn percent_used(&self) -> i64 {
+        (self.spent.cents() as f64 * 100.0 / self.budget.amount.cents() as f64).round() as i64
+    }
+}
+
+impl Display for BudgetStatus<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let used = format!("{}%", self.percent_used());
+        write!(f, "{:<12} | {:<10} | {:<10} | {:<10} | {used:>4}", self.budget.name(), self.budget.amount, self.spent, self.remaining)?;
+        if self.is_over() {
+            write!(f, " (over budget)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::{fmt::Display, path::Path};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use crate::{atomic, money::{Money, Rate}};
+
+/// Currency codes are stored as three uppercase letters (ISO 4217 style, e.g. EUR, USD, BRL)
+pub fn parse_currency(value: &str) -> Result<String, String> {
+    let code = value.trim().to_uppercase();
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid currency code '{value}' (must be three letters, e.g. EUR)"));
+    }
+    Ok(code)
+}
+
+/// One row of the exchange-rate table: on `date`, 1 `from` buys `rate` units of `to`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExchangeRate {
+    pub date: NaiveDate,
+    pub from: String,
+    pub to: String,
+    pub rate: Rate,
+}
+
+impl Display for ExchangeRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let date_str = self.date.format("%Y-%m-%d").to_string();
+        write!(f, "{:<10} | 1 {} = {} {}", date_str, self.from, self.rate, self.to)
+    }
+}
+
+/// Local exchange-rate table, stored in the ledger's `.rates.csv` sidecar file.
+pub struct RateTable {
+    pub rates: Vec<ExchangeRate>,
+}
+
+impl RateTable {
+    pub fn load(file_path: &Path) -> Result<Self, csv::Error> {
+        Ok(RateTable { rates: atomic::load_table(file_path)? })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<(), csv::Error> {
+        atomic::save_table(file_path, &self.rates)
+    }
+
+    /// Adds a rate, replacing the one for the same pair and date if it already exists
+    pub fn set(&mut self, rate: ExchangeRate) {
+        self.rates.retain(|r| !(r.date == rate.date && r.from == rate.from && r.to == rate.to));
+        self.rates.push(rate);
+        self.rates.sort_by(|a, b| (a.date, &a.from, &a.to).cmp(&(b.date, &b.from, &b.to)));
+    }
+
+    /// Converts an amount using the most recent rate published on or before `date`.
+    /// The pair may be stored in either direction; the direct one wins when both exist for the same date.
+    pub fn convert(&self, amount: Money, from: &str, to: &str, date: NaiveDate) -> Result<Money, String> {
+        if from == to {
+            return Ok(amount);
+        }
+        let direct = self.latest(from, to, date);
+        let inverse = self.latest(to, from, date);
+        let converted = match (direct, inverse) {
+            (Some(direct), Some(inverse)) if inverse.date > direct.date => amount.convert_inverse(inverse.rate),
+            (Some(direct), _) => amount.convert(direct.rate),
+            (None, Some(inverse)) => amount.convert_inverse(inverse.rate),
+            (None, None) => {
+                return Err(format!(
+                    "No exchange rate from {from} to {to} on or before {date} (add one with `rates add --from {from} --to {to} --rate <RATE>`)"
+                ))
+            }
+        };
+        converted.ok_or_else(|| format!("{amount} {from} is too large to convert to {to}"))
+    }
+
+    fn latest(&self, from: &str, to: &str, date: NaiveDate) -> Option<&ExchangeRate> {
+        self.rates
+            .iter()
+            .filter(|r| r.from == from && r.to == to && r.date <= date)
+            .max_by_key(|r| r.date)
+    }
+}
@@ -0,0 +1,139 @@
+use std::{error::Error, fs::OpenOptions, io::{self, Write}, path::Path};
+use csv::ByteRecord;
+use crate::{sidecar_path, storage::csv::{parse_row, write_raw_db, BadRow}};
+
+/// What happens to a malformed row when the ledger is repaired
+enum Action {
+    Keep,
+    Fix(ByteRecord),
+    Quarantine,
+}
+
+/// Reports the malformed rows of the ledger. With `quarantine` they are all moved to a sidecar file, with `interactive`
+/// the user decides for each one. Every other row is written back exactly as it was read.
+pub fn run(file_path: &Path, quarantine: bool, interactive: bool) -> Result<(), Box<dyn Error>> {
+    if !file_path.exists() {
+        return Err(format!("{} does not exist", file_path.display()).into());
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b';')
+        .flexible(true)
+        .from_path(file_path)?;
+    let headers = reader.byte_headers()?.clone();
+    let rows = reader
+        .byte_records()
+        .map(|record| record.map(|record| parse_row(&headers, record.clone()).map(|_| record)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bad_count = rows.iter().filter(|row| row.is_err()).count();
+    if bad_count == 0 {
+        println!("No problems found in {}", file_path.display());
+        return Ok(());
+    }
+    println!("Found {bad_count} malformed row(s) in {}:", file_path.display());
+    for bad_row in rows.iter().filter_map(|row| row.as_ref().err()) {
+        println!("  {bad_row}");
+    }
+    if !quarantine && !interactive {
+        println!("Run `doctor --quarantine` or `doctor --interactive` to repair them.");
+        return Ok(());
+    }
+
+    let mut kept_records = Vec::new();
+    let mut quarantined = Vec::new();
+    let mut fixed = 0;
+    for row in rows {
+        match row {
+            Ok(record) => kept_records.push(record),
+            Err(bad_row) => {
+                let action = if interactive { ask_action(&headers, &bad_row)? } else { Action::Quarantine };
+                match action {
+                    Action::Keep => kept_records.push(bad_row.record),
+                    Action::Fix(record) => {
+                        kept_records.push(record);
+                        fixed += 1;
+                    }
+                    Action::Quarantine => quarantined.push(bad_row.record),
+                }
+            }
+        }
+    }
+    if fixed == 0 && quarantined.is_empty() {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+
+    // Quarantined rows are saved before the ledger is rewritten, so a failure in between can't lose them
+    if !quarantined.is_empty() {
+        let quarantine_path = sidecar_path(file_path, "quarantine");
+        append_quarantine(&quarantine_path, &headers, &quarantined)?;
+        println!("Moved {} row(s) to {}", quarantined.len(), quarantine_path.display());
+    }
+    write_raw_db(file_path, &headers, &kept_records)?;
+    if fixed > 0 {
+        println!("Fixed {fixed} row(s)");
+    }
+    Ok(())
+}
+
+fn ask_action(headers: &ByteRecord, bad_row: &BadRow) -> Result<Action, Box<dyn Error>> {
+    println!("\n{bad_row}");
+    loop {
+        let Some(answer) = prompt("[f]ix, [q]uarantine or [k]eep this row? [k] ")? else {
+            return Ok(Action::Keep);
+        };
+        match answer.to_lowercase().as_str() {
+            "f" | "fix" => {
+                let columns: Vec<String> = headers.iter().map(|h| String::from_utf8_lossy(h).into_owned()).collect();
+                println!("Type the corrected row ({}):", columns.join(";"));
+                let Some(line) = prompt("> ")? else {
+                    return Ok(Action::Keep);
+                };
+                let record = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .delimiter(b';')
+                    .flexible(true)
+                    .from_reader(line.as_bytes())
+                    .byte_records()
+                    .next()
+                    .transpose()?
+                    .unwrap_or_default();
+                // The fixed row is only accepted once it can actually be read back
+                match parse_row(headers, record.clone()) {
+                    Ok(_) => return Ok(Action::Fix(record)),
+                    Err(still_bad) => println!("The corrected row is still invalid: {}", still_bad.error),
+                }
+            }
+            "q" | "quarantine" => return Ok(Action::Quarantine),
+            "" | "k" | "keep" => return Ok(Action::Keep),
+            _ => println!("Please answer f, q or k."),
+        }
+    }
+}
+
+/// Reads one trimmed line from stdin, `None` when stdin is closed
+pub(crate) fn prompt(message: &str) -> io::Result<Option<String>> {
+    print!("{message}");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+/// The quarantine file starts with the ledger's header, so its rows can be fixed and pasted back later
+fn append_quarantine(quarantine_path: &Path, headers: &ByteRecord, records: &[ByteRecord]) -> Result<(), csv::Error> {
+    let is_new = !quarantine_path.exists() || quarantine_path.metadata()?.len() == 0;
+    let file = OpenOptions::new().create(true).append(true).open(quarantine_path)?;
+    let mut writer = csv::WriterBuilder::new().delimiter(b';').flexible(true).from_writer(file);
+    if is_new {
+        writer.write_byte_record(headers)?;
+    }
+    for record in records {
+        writer.write_byte_record(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
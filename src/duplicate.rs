@@ -0,0 +1,131 @@
+use clap::ValueEnum;
+use crate::{doctor::prompt, Expense};
+
+/// What to do with a new record that looks like one already in the ledger
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Add it, with a warning
+    Warn,
+    /// Don't add it
+    Skip,
+    /// Add it without saying anything
+    Keep,
+    /// Ask for each one
+    Ask,
+}
+
+/// Descriptions at least this similar (from 0 to 1) are considered the same
+const SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// The first record of the ledger with the same date, amount, currency and kind as `candidate` and a similar description
+pub fn find<'a>(candidate: &Expense, existing: &'a [Expense]) -> Option<&'a Expense> {
+    existing.iter().find(|record| {
+        record.date == candidate.date
+            && record.amount == candidate.amount
+            && record.currency == candidate.currency
+            && record.kind == candidate.kind
+            && similar(&record.description, &candidate.description)
+    })
+}
+
+/// Descriptions are compared ignoring case, punctuation and spacing, so "NETFLIX.COM" matches "Netflix com".
+/// One containing the other also counts, since banks often add references (e.g. "Netflix" and "NETFLIX.COM 8814").
+fn similar(a: &str, b: &str) -> bool {
+    let (a, b) = (normalize(a), normalize(b));
+    if a.is_empty() || b.is_empty() {
+        return a == b;
+    }
+    if a.contains(&b) || b.contains(&a) {
+        return true;
+    }
+    let length = a.chars().count().max(b.chars().count());
+    1.0 - levenshtein(&a, &b) as f64 / length as f64 >= SIMILARITY_THRESHOLD
+}
+
+fn normalize(description: &str) -> String {
+    description.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Number of single character insertions, deletions or substitutions to turn `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Applies the policy to a record that looks like `duplicate`, returning whether it should still be added
+pub fn keep(candidate: &Expense, duplicate: &Expense, policy: OnDuplicate) -> std::io::Result<bool> {
+    let message = format!("'{}' on {} looks like a duplicate of expense with ID {}", candidate.description, candidate.date, duplicate.id);
+    match policy {
+        OnDuplicate::Warn => {
+            eprintln!("Warning: {message}");
+            Ok(true)
+        }
+        OnDuplicate::Skip => {
+            println!("Skipped: {message}");
+            Ok(false)
+        }
+        OnDuplicate::Keep => Ok(true),
+        OnDuplicate::Ask => {
+            println!("{message}:");
+            println!("{duplicate}");
+            let answer = prompt("Add it anyway? [y/N] ")?;
+            Ok(matches!(answer.as_deref(), Some("y" | "Y" | "yes")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use crate::{money::Money, Kind};
+    use super::*;
+
+    fn expense(id: u32, description: &str, cents: i64, day: u32) -> Expense {
+        let date = NaiveDate::from_ymd_opt(2025, 1, day);
+        Expense::new(id, description.to_string(), Money::from_cents(cents), date, None, Vec::new(), "USD".to_string())
+    }
+
+    #[test]
+    fn counts_edits_between_descriptions() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("flaw", "lawn"), 2);
+        assert_eq!(levenshtein("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn similar_descriptions_ignore_case_punctuation_and_references() {
+        assert!(similar("NETFLIX.COM", "Netflix com"));
+        assert!(similar("Netflix", "NETFLIX.COM 8814"));
+        // 1 edit in 10 characters is 0.9 similar, 2 edits is exactly the threshold, 3 is below it
+        assert!(similar("supermarkt", "supermarket"));
+        assert!(similar("abcdefghij", "abcdefghXY"));
+        assert!(!similar("abcdefghij", "abcdefgXYZ"));
+        assert!(!similar("Rent", "Gym"));
+        assert!(similar("", "..."));
+        assert!(!similar("", "Rent"));
+    }
+
+    #[test]
+    fn duplicates_have_the_same_date_amount_currency_and_kind() {
+        let mut income = expense(4, "Netflix", 999, 5);
+        income.kind = Kind::Income;
+        let mut euros = expense(5, "Netflix", 999, 5);
+        euros.currency = "EUR".to_string();
+        let existing = [expense(1, "Netflix", 999, 6), expense(2, "Netflix", 1099, 5), income, euros, expense(3, "NETFLIX.COM 8814", 999, 5)];
+        assert_eq!(find(&expense(0, "Netflix", 999, 5), &existing).map(|record| record.id), Some(3));
+        assert!(find(&expense(0, "Spotify", 999, 5), &existing).is_none());
+        assert!(find(&expense(0, "Netflix", 999, 7), &existing).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::str::FromStr;
+use chrono::{Datelike, Days, Month, Months, NaiveDate};
+use num_traits::cast::FromPrimitive;
+use crate::{parse_category, parse_tag, query::{Comparison, Query, TextField, TextTest}, Expense};
+
+/// Options shared by the commands that operate on a subset of the expenses (List, Summary)
+#[derive(clap::Args, Debug, Clone)]
+pub struct Filter {
+    /// Only expenses of this month (of the current year, unless --year is given)
+    #[arg(short = 'm', long, conflicts_with = "quarter")]
+    pub month: Option<u32>,
+    /// Only expenses of this quarter, 1 to 4 (of the current year, unless --year is given)
+    #[arg(short = 'q', long, value_parser = clap::value_parser!(u32).range(1..=4))]
+    pub quarter: Option<u32>,
+    /// Only expenses of this year (or of the given month/quarter of this year)
+    #[arg(short = 'y', long, value_parser = clap::value_parser!(i32).range(1..=9999))]
+    pub year: Option<i32>,
+    /// Only expenses on or after this date (%Y-%m-%d)
+    #[arg(long, conflicts_with_all = ["month", "quarter", "year"])]
+    pub from: Option<NaiveDate>,
+    /// Only expenses on or before this date (%Y-%m-%d)
+    #[arg(long, conflicts_with_all = ["month", "quarter", "year"])]
+    pub to: Option<NaiveDate>,
+    /// Only expenses of the last N days, weeks or months up to today, e.g. "30d", "2 weeks", "3months"
+    #[arg(short = 'l', long, conflicts_with_all = ["month", "quarter", "year", "from", "to"])]
+    pub last: Option<Last>,
+    #[arg(short = 'c', long, value_parser = parse_category)]
+    pub category: Option<String>,
+    /// Only expenses with this tag (can be repeated, all tags must be present)
+    #[arg(short = 't', long = "tag", value_parser = parse_tag)]
+    pub tags: Vec<String>,
+    /// Only expenses without this tag (can be repeated)
+    #[arg(long = "not-tag", value_parser = parse_tag)]
+    pub not_tags: Vec<String>,
+    /// Only expenses matching a query, e.g. "amount > 100 and description ~ 'uber'" (see the README for the syntax)
+    #[arg(short = 'w', long = "where")]
+    pub condition: Option<Query>,
+}
+
+/// Length of a period ending today, as given to --last
+#[derive(Debug, Clone, Copy)]
+pub enum Last {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+}
+
+impl FromStr for Last {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let count: u32 = count.parse().map_err(|_| format!("Invalid period '{s}' (expected e.g. 30d, 2w or 3m)"))?;
+        if count == 0 {
+            return Err("The period must be at least 1".into());
+        }
+        match unit.trim() {
+            "d" | "day" | "days" => Ok(Last::Days(count)),
+            "w" | "week" | "weeks" => Ok(Last::Weeks(count)),
+            "m" | "month" | "months" => Ok(Last::Months(count)),
+            _ => Err(format!("Invalid period unit '{}' (expected days, weeks or months)", unit.trim())),
+        }
+    }
+}
+
+impl Filter {
+    /// Selects every expense of a single month
+    pub fn for_month(year: i32, month: u32) -> Filter {
+        Filter {
+            month: Some(month),
+            quarter: None,
+            year: Some(year),
+            from: None,
+            to: None,
+            last: None,
+            category: None,
+            tags: Vec::new(),
+            not_tags: Vec::new(),
+            condition: None,
+        }
+    }
+
+    /// First and last day (both inclusive) selected by the date options, `None` meaning unbounded
+    pub fn date_range(&self, today: NaiveDate) -> Result<(Option<NaiveDate>, Option<NaiveDate>), String> {
+        let year = self.year.unwrap_or(today.year());
+        let whole_months = |first_month: u32, count: u32| {
+            let start = NaiveDate::from_ymd_opt(year, first_month, 1).ok_or("Invalid date")?;
+            let end = start + Months::new(count) - Days::new(1);
+            Ok::<_, String>((Some(start), Some(end)))
+        };
+        if let Some(month) = self.month {
+            if !(1..=12).contains(&month) {
+                return Err("Invalid month (must be a number between 1 and 12)".into());
+            }
+            return whole_months(month, 1);
+        }
+        if let Some(quarter) = self.quarter {
+            return whole_months((quarter - 1) * 3 + 1, 3);
+        }
+        if self.year.is_some() {
+            return whole_months(1, 12);
+        }
+        if let Some(last) = self.last {
+            // The period ends today, e.g. the last 1 day is only today
+            let start = match last {
+                Last::Days(days) => today.checked_sub_days(Days::new(days as u64 - 1)),
+                Last::Weeks(weeks) => today.checked_sub_days(Days::new(weeks as u64 * 7 - 1)),
+                Last::Months(months) => today.checked_sub_months(Months::new(months)).and_then(|start| start.checked_add_days(Days::new(1))),
+            };
+            let start = start.ok_or_else(|| "--last goes back further than the earliest supported date".to_string())?;
+            return Ok((Some(start), Some(today)));
+        }
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(format!("--from ({from}) must not be after --to ({to})"));
+            }
+        }
+        Ok((self.from, self.to))
+    }
+
+    /// Describes the selected period for the summary, e.g. "for March 2024" (empty when there are no date options)
+    pub fn period_label(&self) -> String {
+        let month_name = |month: u32| Month::from_u32(month).map_or("", |month| month.name());
+        match (self.month, self.quarter, self.year, self.last, self.from, self.to) {
+            (Some(month), _, None, ..) => format!(" for {}", month_name(month)),
+            (Some(month), _, Some(year), ..) => format!(" for {} {year}", month_name(month)),
+            (_, Some(quarter), None, ..) => format!(" for Q{quarter}"),
+            (_, Some(quarter), Some(year), ..) => format!(" for Q{quarter} {year}"),
+            (_, _, Some(year), ..) => format!(" for {year}"),
+            (_, _, _, Some(Last::Days(n)), ..) => format!(" for the last {n} day(s)"),
+            (_, _, _, Some(Last::Weeks(n)), ..) => format!(" for the last {n} week(s)"),
+            (_, _, _, Some(Last::Months(n)), ..) => format!(" for the last {n} month(s)"),
+            (.., Some(from), Some(to)) => format!(" from {from} to {to}"),
+            (.., Some(from), None) => format!(" since {from}"),
+            (.., None, Some(to)) => format!(" until {to}"),
+            _ => String::new(),
+        }
+    }
+
+    /// Every option combined into a single query: an expense must pass all of them
+    pub fn to_query(&self, today: NaiveDate) -> Result<Query, String> {
+        let mut query = Query::All;
+        let (start, end) = self.date_range(today)?;
+        if let Some(start) = start {
+            query = query.and(Query::Date(Comparison::Ge, start));
+        }
+        if let Some(end) = end {
+            query = query.and(Query::Date(Comparison::Le, end));
+        }
+        if let Some(category) = &self.category {
+            query = query.and(Query::Text(TextField::Category, TextTest::Equals(category.clone())));
+        }
+        // Tag expressions: every --tag must be present and no --not-tag may be present
+        for tag in &self.tags {
+            query = query.and(Query::Tag(TextTest::Equals(tag.clone())));
+        }
+        for tag in &self.not_tags {
+            query = query.and(Query::Not(Box::new(Query::Tag(TextTest::Equals(tag.clone())))));
+        }
+        if let Some(condition) = &self.condition {
+            query = query.and(condition.clone());
+        }
+        Ok(query)
+    }
+}
+
+pub fn filter_records(records: &mut Vec<Expense>, filter: &Filter) -> Result<(), String> {
+    let query = filter.to_query(chrono::Local::now().date_naive())?;
+    records.retain(|expense| query.matches(expense));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn filter() -> Filter {
+        Filter { month: None, year: None, ..Filter::for_month(0, 1) }
+    }
+
+    fn range(filter: Filter, today: NaiveDate) -> (Option<NaiveDate>, Option<NaiveDate>) {
+        filter.date_range(today).unwrap()
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert!(matches!("30d".parse(), Ok(Last::Days(30))));
+        assert!(matches!("2 weeks".parse(), Ok(Last::Weeks(2))));
+        assert!(matches!("3Months".parse(), Ok(Last::Months(3))));
+        assert!("0d".parse::<Last>().is_err());
+        assert!("5y".parse::<Last>().is_err());
+        assert!("d".parse::<Last>().is_err());
+    }
+
+    #[test]
+    fn last_periods_end_today() {
+        let today = date(2025, 3, 31);
+        assert_eq!(range(Filter { last: Some(Last::Days(1)), ..filter() }, today), (Some(today), Some(today)));
+        assert_eq!(range(Filter { last: Some(Last::Days(31)), ..filter() }, today), (Some(date(2025, 3, 1)), Some(today)));
+        assert_eq!(range(Filter { last: Some(Last::Weeks(2)), ..filter() }, today), (Some(date(2025, 3, 18)), Some(today)));
+        // A month before March 31st is February 28th, so the last month starts on March 1st
+        assert_eq!(range(Filter { last: Some(Last::Months(1)), ..filter() }, today), (Some(date(2025, 3, 1)), Some(today)));
+        assert_eq!(range(Filter { last: Some(Last::Months(2)), ..filter() }, date(2025, 1, 15)), (Some(date(2024, 11, 16)), Some(date(2025, 1, 15))));
+        assert!(Filter { last: Some(Last::Days(4_000_000_000)), ..filter() }.date_range(today).is_err());
+    }
+
+    #[test]
+    fn whole_months_quarters_and_years() {
+        let today = date(2024, 6, 10);
+        assert_eq!(range(Filter { month: Some(2), ..filter() }, today), (Some(date(2024, 2, 1)), Some(date(2024, 2, 29))));
+        assert_eq!(range(Filter::for_month(2023, 2), today), (Some(date(2023, 2, 1)), Some(date(2023, 2, 28))));
+        assert_eq!(range(Filter { month: Some(12), year: Some(2025), ..filter() }, today), (Some(date(2025, 12, 1)), Some(date(2025, 12, 31))));
+        assert_eq!(range(Filter { quarter: Some(4), ..filter() }, today), (Some(date(2024, 10, 1)), Some(date(2024, 12, 31))));
+        assert_eq!(range(Filter { year: Some(2023), ..filter() }, today), (Some(date(2023, 1, 1)), Some(date(2023, 12, 31))));
+        assert!(Filter { month: Some(13), ..filter() }.date_range(today).is_err());
+    }
+
+    #[test]
+    fn explicit_ranges() {
+        let today = date(2024, 6, 10);
+        assert_eq!(range(filter(), today), (None, None));
+        assert_eq!(range(Filter { from: Some(date(2024, 1, 1)), ..filter() }, today), (Some(date(2024, 1, 1)), None));
+        assert!(Filter { from: Some(date(2024, 2, 1)), to: Some(date(2024, 1, 1)), ..filter() }.date_range(today).is_err());
+    }
+}
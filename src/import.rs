@@ -0,0 +1,338 @@
+use std::{fmt::Display, path::Path};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::{atomic, currency::parse_currency, money::Money, parse_category, Expense, Kind};
+
+/// File formats of the statements `import` reads
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementFormat {
+    /// CSV, read with a profile
+    Csv,
+    /// OFX or QFX (Quicken's variant of OFX)
+    Ofx,
+    /// QIF, the older format of Quicken and other personal-finance tools
+    Qif,
+}
+
+impl StatementFormat {
+    /// Guessed from the extension of the file, CSV unless it is .ofx, .qfx or .qif
+    pub fn detect(file_path: &Path) -> StatementFormat {
+        let extension = file_path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ofx" | "qfx") => StatementFormat::Ofx,
+            Some("qif") => StatementFormat::Qif,
+            _ => StatementFormat::Csv,
+        }
+    }
+}
+
+/// Which amounts of a statement are money spent
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignConvention {
+    /// Negative amounts are expenses and positive ones income (most bank accounts)
+    #[default]
+    NegativeIsExpense,
+    /// Positive amounts are expenses and negative ones income (most credit cards)
+    PositiveIsExpense,
+}
+
+/// How to read the CSV export of one bank. Columns are given by header name, or by number (from 1).
+#[derive(clap::Args, Debug, Clone, Deserialize, Serialize)]
+pub struct Profile {
+    #[arg(short = 'n', long)]
+    pub name: String,
+    /// Column separator
+    #[arg(long, default_value_t = ',')]
+    pub delimiter: char,
+    /// Lines before the header line (e.g. account details at the top of the export)
+    #[arg(long, default_value_t = 0)]
+    pub skip_lines: usize,
+    #[arg(long)]
+    pub date_column: String,
+    /// Format of the dates, e.g. "%d/%m/%Y" (see chrono's strftime)
+    #[arg(long, default_value = "%Y-%m-%d")]
+    pub date_format: String,
+    #[arg(long)]
+    pub description_column: String,
+    /// Column with signed amounts (either this or --debit-column/--credit-column)
+    #[arg(long, required_unless_present_any = ["debit_column", "credit_column"])]
+    pub amount_column: Option<String>,
+    /// Column with the money spent, for statements that split amounts in two columns
+    #[arg(long, conflicts_with = "amount_column")]
+    pub debit_column: Option<String>,
+    /// Column with the money received, for statements that split amounts in two columns
+    #[arg(long, conflicts_with = "amount_column")]
+    pub credit_column: Option<String>,
+    /// Decimal separator of the amounts; the other one of `.` and `,` is read as a thousands separator
+    #[arg(long, default_value_t = '.')]
+    pub decimal_separator: char,
+    #[arg(long, value_enum, default_value_t = SignConvention::NegativeIsExpense)]
+    pub sign: SignConvention,
+    #[arg(long)]
+    pub category_column: Option<String>,
+    #[arg(long)]
+    pub currency_column: Option<String>,
+    /// Currency of the statement when it has no currency column (defaults to the base currency)
+    #[arg(short = 'u', long, value_parser = parse_currency)]
+    pub currency: Option<String>,
+    /// Account the imported records are assigned to
+    #[arg(short = 'a', long, value_parser = parse_category)]
+    pub account: Option<String>,
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let amount = match (&self.amount_column, &self.debit_column, &self.credit_column) {
+            (Some(amount), ..) => format!("amount={amount}"),
+            (None, debit, credit) => format!("debit={} credit={}", debit.as_deref().unwrap_or("-"), credit.as_deref().unwrap_or("-")),
+        };
+        write!(
+            f,
+            "{:<12} | date={} ({}) description={} {amount} | decimal '{}', delimiter '{}'",
+            self.name, self.date_column, self.date_format, self.description_column, self.decimal_separator, self.delimiter.escape_default()
+        )
+    }
+}
+
+/// Import profiles, kept in the `.profiles.csv` file next to the ledger so every bank is only described once.
+pub struct ProfileTable {
+    pub profiles: Vec<Profile>,
+}
+
+impl ProfileTable {
+    pub fn load(file_path: &Path) -> Result<Self, csv::Error> {
+        Ok(ProfileTable { profiles: atomic::load_table(file_path)? })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<(), csv::Error> {
+        atomic::save_table(file_path, &self.profiles)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// Adds a profile, replacing the one with the same name if it already exists
+    pub fn set(&mut self, profile: Profile) {
+        self.remove(&profile.name);
+        self.profiles.push(profile);
+    }
+
+    /// Whether there was a profile to remove
+    pub fn remove(&mut self, name: &str) -> bool {
+        let count = self.profiles.len();
+        self.profiles.retain(|profile| profile.name != name);
+        self.profiles.len() != count
+    }
+}
+
+/// A row of the statement that could not be imported
+pub struct SkippedRow {
+    pub line: u64,
+    pub error: String,
+}
+
+/// Reads a statement with a profile. Rows that can't be read (e.g. a "Total" line at the end) are skipped and reported.
+pub fn read_statement(file_path: &Path, profile: &Profile, base_currency: &str) -> Result<(Vec<Expense>, Vec<SkippedRow>), Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(file_path).map_err(|e| format!("Could not read {}: {e}", file_path.display()))?;
+    let content = String::from_utf8_lossy(&bytes);
+    // Keeps the line endings, so the line numbers of the reader can be offset by the skipped lines
+    let content: String = content.split_inclusive('\n').skip(profile.skip_lines).collect();
+    let delimiter = u8::try_from(profile.delimiter).map_err(|_| "The delimiter must be an ASCII character")?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(delimiter)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| -> Result<usize, String> {
+        if let Ok(number) = name.parse::<usize>() {
+            return number.checked_sub(1).ok_or_else(|| "Column numbers start at 1".to_string());
+        }
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("The statement has no column '{name}' (its columns are: {})", headers.iter().collect::<Vec<_>>().join(", ")))
+    };
+    let optional_column = |name: &Option<String>| name.as_deref().map(column).transpose();
+    let columns = Columns {
+        date: column(&profile.date_column)?,
+        description: column(&profile.description_column)?,
+        amount: optional_column(&profile.amount_column)?,
+        debit: optional_column(&profile.debit_column)?,
+        credit: optional_column(&profile.credit_column)?,
+        category: optional_column(&profile.category_column)?,
+        currency: optional_column(&profile.currency_column)?,
+    };
+    let currency = profile.currency.clone().unwrap_or_else(|| base_currency.to_string());
+    let (mut records, mut skipped) = (Vec::new(), Vec::new());
+    for row in reader.records() {
+        let row = row?;
+        let line = row.position().map_or(0, |position| position.line()) + profile.skip_lines as u64;
+        if row.iter().all(str::is_empty) {
+            continue;
+        }
+        match read_row(&row, &columns, profile, &currency) {
+            Ok(record) => records.push(record),
+            Err(error) => skipped.push(SkippedRow { line, error }),
+        }
+    }
+    Ok((records, skipped))
+}
+
+struct Columns {
+    date: usize,
+    description: usize,
+    amount: Option<usize>,
+    debit: Option<usize>,
+    credit: Option<usize>,
+    category: Option<usize>,
+    currency: Option<usize>,
+}
+
+fn read_row(row: &csv::StringRecord, columns: &Columns, profile: &Profile, currency: &str) -> Result<Expense, String> {
+    let cell = |index: usize| row.get(index).ok_or_else(|| format!("missing column {}", index + 1));
+    let optional_cell = |index: Option<usize>| index.and_then(|index| row.get(index)).filter(|value| !value.is_empty());
+    let date = NaiveDate::parse_from_str(cell(columns.date)?, &profile.date_format)
+        .map_err(|_| format!("'{}' is not a date in the format {}", cell(columns.date).unwrap_or_default(), profile.date_format))?;
+    let description = cell(columns.description)?.to_string();
+    // A signed amount, positive when money was received
+    let received = match columns.amount {
+        Some(index) => {
+            let amount = parse_amount(cell(index)?, profile.decimal_separator)?;
+            if profile.sign == SignConvention::NegativeIsExpense { amount } else { -amount }
+        }
+        None => {
+            let debit = optional_cell(columns.debit).map(|value| parse_amount(value, profile.decimal_separator)).transpose()?;
+            let credit = optional_cell(columns.credit).map(|value| parse_amount(value, profile.decimal_separator)).transpose()?;
+            if debit.is_none() && credit.is_none() {
+                return Err("no amount".into());
+            }
+            // Some banks write debits as negative numbers too
+            credit.unwrap_or_default().checked_sub(debit.map_or(Money::default(), |debit| Money::from_cents(debit.cents().abs())))?
+        }
+    };
+    let currency = match optional_cell(columns.currency) {
+        Some(value) => parse_currency(value)?,
+        None => currency.to_string(),
+    };
+    let category = optional_cell(columns.category).map(|value| value.trim().to_lowercase());
+    let amount = Money::from_cents(received.cents().abs());
+    let mut record = Expense::new(0, description, amount, Some(date), category, Vec::new(), currency);
+    if received > Money::default() {
+        record.kind = Kind::Income;
+    }
+    record.account = profile.account.clone();
+    Ok(record)
+}
+
+/// Reads an amount as written by a bank: thousands separators, currency symbols and spaces are ignored,
+/// and amounts in parentheses are negative (accounting style)
+pub(crate) fn parse_amount(value: &str, decimal_separator: char) -> Result<Money, String> {
+    let negative = value.starts_with('(') && value.ends_with(')');
+    let mut cleaned: String = value.chars().filter(|c| c.is_ascii_digit() || *c == '-' || *c == '+' || *c == decimal_separator).collect();
+    // Some banks put the minus sign after the number ("12.50-")
+    if let Some(unsigned) = cleaned.strip_suffix('-') {
+        cleaned = format!("-{unsigned}");
+    }
+    let amount: Money = cleaned.replace(decimal_separator, ".").parse().map_err(|_| format!("'{value}' is not an amount"))?;
+    Ok(if negative { -amount } else { amount })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profile {
+        Profile {
+            name: "bank".to_string(),
+            delimiter: ',',
+            skip_lines: 0,
+            date_column: "Date".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            description_column: "Description".to_string(),
+            amount_column: Some("Amount".to_string()),
+            debit_column: None,
+            credit_column: None,
+            decimal_separator: '.',
+            sign: SignConvention::NegativeIsExpense,
+            category_column: None,
+            currency_column: None,
+            currency: None,
+            account: None,
+        }
+    }
+
+    /// Cents, kind and description of a record
+    type Record = (i64, Kind, String);
+
+    fn read(profile: &Profile, statement: &str) -> (Vec<Record>, Vec<(u64, String)>) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), statement).unwrap();
+        let (records, skipped) = read_statement(file.path(), profile, "USD").unwrap();
+        (
+            records.into_iter().map(|record| (record.amount.cents(), record.kind, record.description)).collect(),
+            skipped.into_iter().map(|row| (row.line, row.error)).collect(),
+        )
+    }
+
+    #[test]
+    fn reads_amounts_with_either_decimal_separator() {
+        assert_eq!(parse_amount("1,234.56", '.'), Ok(Money::from_cents(123456)));
+        assert_eq!(parse_amount("1.234,56", ','), Ok(Money::from_cents(123456)));
+        assert_eq!(parse_amount("1 234,5 €", ','), Ok(Money::from_cents(123450)));
+        assert_eq!(parse_amount("$-12.50", '.'), Ok(Money::from_cents(-1250)));
+        assert_eq!(parse_amount("12.50-", '.'), Ok(Money::from_cents(-1250)));
+        assert_eq!(parse_amount("(12.50)", '.'), Ok(Money::from_cents(-1250)));
+        assert!(parse_amount("n/a", '.').is_err());
+    }
+
+    #[test]
+    fn negative_amounts_are_expenses_by_default() {
+        let (records, skipped) = read(&profile(), "Date,Description,Amount\n2025-01-03,Lunch,-12.50\n2025-01-31,Salary,\"3,000.00\"\n");
+        assert_eq!(records, [(1250, Kind::Expense, "Lunch".to_string()), (300000, Kind::Income, "Salary".to_string())]);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn positive_amounts_are_expenses_on_credit_cards() {
+        let mut card = profile();
+        card.sign = SignConvention::PositiveIsExpense;
+        card.delimiter = ';';
+        card.decimal_separator = ',';
+        let (records, _) = read(&card, "Date;Description;Amount\n2025-01-03;Lunch;12,50\n2025-01-20;Payment;-1.000,00\n");
+        assert_eq!(records, [(1250, Kind::Expense, "Lunch".to_string()), (100000, Kind::Income, "Payment".to_string())]);
+    }
+
+    #[test]
+    fn reads_debit_and_credit_columns() {
+        let mut split = profile();
+        split.amount_column = None;
+        split.debit_column = Some("Debit".to_string());
+        split.credit_column = Some("4".to_string());
+        let statement = "Date,Description,Debit,Credit\n2025-01-03,Lunch,12.50,\n2025-01-04,Taxi,-20.00,\n2025-01-31,Salary,,3000.00\n";
+        let (records, _) = read(&split, statement);
+        assert_eq!(
+            records,
+            [(1250, Kind::Expense, "Lunch".to_string()), (2000, Kind::Expense, "Taxi".to_string()), (300000, Kind::Income, "Salary".to_string())]
+        );
+    }
+
+    #[test]
+    fn skips_rows_without_an_amount() {
+        let mut split = profile();
+        split.amount_column = None;
+        split.debit_column = Some("Debit".to_string());
+        split.credit_column = Some("Credit".to_string());
+        split.skip_lines = 2;
+        let statement = "Account 1234\n\nDate,Description,Debit,Credit\n2025-01-03,Lunch,12.50,\n2025-01-04,Pending,,\nTotal,,12.50,\n";
+        let (records, skipped) = read(&split, statement);
+        assert_eq!(records, [(1250, Kind::Expense, "Lunch".to_string())]);
+        assert_eq!(skipped[0], (5, "no amount".to_string()));
+        assert_eq!(skipped[1].0, 6);
+        assert!(skipped[1].1.contains("is not a date"));
+    }
+}
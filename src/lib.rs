@@ -1,8 +1,40 @@
-use std::{fmt::Display, fs::File, io::Write, path::Path, error::Error};
+use std::{collections::BTreeSet, fmt::Display, path::{Path, PathBuf}, error::Error, time::Duration};
 use clap::{Parser, Subcommand}; 
-use chrono::{NaiveDate, Datelike, Month}; 
+use chrono::{Datelike, NaiveDate}; 
 use serde::{Deserialize, Serialize};
-use num_traits::cast::FromPrimitive;
+use money::{Money, Rate};
+use currency::{parse_currency, ExchangeRate, RateTable};
+use budget::{Budget, BudgetTable};
+use recurring::{Frequency, Rule, RuleTable, ShortMonth};
+use account::{Account, AccountTable};
+use split::{parse_name, Split};
+use import::{Profile, ProfileTable, StatementFormat};
+use duplicate::OnDuplicate;
+use storage::Backend;
+use filter::{filter_records, Filter};
+use output::Format;
+use report::{ExpenseRow, GroupBy};
+use stats::{OutlierGroup, Stats};
+
+mod money;
+mod currency;
+mod doctor;
+mod atomic;
+mod lock;
+mod storage;
+mod filter;
+mod query;
+mod output;
+mod report;
+mod stats;
+mod budget;
+mod recurring;
+mod account;
+mod split;
+mod import;
+mod duplicate;
+mod ofx;
+mod qif;
 
 
 #[derive(Parser, Debug)]
@@ -10,6 +42,18 @@ use num_traits::cast::FromPrimitive;
 struct Args {
     #[command(subcommand)]
     cmd: Commands, 
+    /// Currency used for new expenses by default and that summaries are converted to
+    #[arg(long, global = true, env = "EXPENSE_TRACKER_BASE_CURRENCY", default_value = "USD", value_parser = parse_currency)]
+    base_currency: String,
+    /// Ledger file (defaults to expenses.csv in the user's data directory, e.g. ~/.local/share/expense-tracker)
+    #[arg(long, global = true, env = "EXPENSE_TRACKER_FILE")]
+    file: Option<PathBuf>,
+    /// Storage backend of the ledger (detected from the file extension by default: .db, .sqlite and .sqlite3 are SQLite)
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+    /// Seconds to wait for other running commands to release the ledger
+    #[arg(long, global = true, env = "EXPENSE_TRACKER_LOCK_TIMEOUT", default_value_t = 10)]
+    lock_timeout: u64,
 }
 
 /// Subcommands (Add, Delete, Etc.) and their Optional/Mandatory arguments
@@ -18,32 +62,445 @@ enum Commands {
     Add {
         #[arg(short = 'k', long)]
         description: String, 
-        #[arg(short = 'v', long, default_value_t = 0.0)]
-        amount: f32, 
+        #[arg(short = 'v', long, default_value_t = Money::default())]
+        amount: Money, 
         #[arg(short = 'd', long)]
         date: Option<NaiveDate>, 
+        #[arg(short = 'c', long, value_parser = parse_category)]
+        category: Option<String>,
+        /// Tag to attach to the expense (can be repeated)
+        #[arg(short = 't', long = "tag", value_parser = parse_tag)]
+        tags: Vec<String>,
+        /// Currency of the amount (defaults to the base currency)
+        #[arg(short = 'u', long, value_parser = parse_currency)]
+        currency: Option<String>,
+        /// Whether money was spent, received or moved between two accounts
+        #[arg(long, value_enum, default_value_t = Kind::Expense)]
+        kind: Kind,
+        /// Account the money is paid from (or received into, for income)
+        #[arg(short = 'a', long, value_parser = parse_category)]
+        account: Option<String>,
+        /// Account the money of a transfer goes to
+        #[arg(long, value_parser = parse_category)]
+        to_account: Option<String>,
+        /// Person who paid an expense shared with others
+        #[arg(short = 'p', long, value_parser = parse_name)]
+        paid_by: Option<String>,
+        /// How the expense is shared, e.g. "alice,bob", "shares:alice=2,bob=1" or "exact:alice=30,bob=20" (see the README)
+        #[arg(long, requires = "paid_by")]
+        split: Option<Split>,
+        /// What to do when the ledger already has an expense with the same date and amount and a similar description
+        #[arg(long, value_enum, default_value_t = OnDuplicate::Warn)]
+        on_duplicate: OnDuplicate,
     }, 
     Update {
         #[arg(short, long)]
         id: u32, 
-        #[arg(short = 'k', long)]
-        description: Option<String>,
-        #[arg(short = 'v', long)]
-        amount: Option<f32>,
-        #[arg(short = 'd', long)]
-        date: Option<NaiveDate>, 
+        #[command(flatten)]
+        changes: Changes,
     },
     Delete {
         #[arg(short, long)]
         id: u32
     },
     List {
-        #[arg(short = 'm', long)]
-        month: Option<u32>,
+        #[command(flatten)]
+        filter: Filter,
+        #[command(flatten)]
+        order: Order,
+        /// Output format (see the README for the schema of the machine-readable formats)
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
     },
     Summary {
-        #[arg(short = 'm', long)]
+        #[command(flatten)]
+        filter: Filter,
+        /// Break the total down per category
+        #[arg(long)]
+        by_category: bool,
+        /// Show the total, count and average expense of each period, category or tag instead
+        #[arg(short, long, value_enum, conflicts_with = "by_category")]
+        group_by: Option<GroupBy>,
+        /// Output format (see the README for the schema of the machine-readable formats)
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Statistics of the expenses (in the base currency) and the expenses far above the typical ones
+    Stats {
+        #[command(flatten)]
+        filter: Filter,
+        /// Compare each expense to the others of its category or of its month to find outliers
+        #[arg(long, value_enum, default_value = "category")]
+        outliers_by: OutlierGroup,
+        /// How far above the typical expenses an outlier is, in interquartile ranges above the third quartile
+        #[arg(long, default_value_t = 1.5)]
+        outlier_factor: f64,
+    },
+    /// Report malformed rows of the ledger and optionally repair them
+    Doctor {
+        /// Move every malformed row to the ledger's .quarantine.csv file
+        #[arg(short, long, conflicts_with = "interactive")]
+        quarantine: bool,
+        /// Decide for each malformed row whether to fix it, quarantine it or keep it
+        #[arg(short, long)]
+        interactive: bool,
+    },
+    /// Replace the ledger with the backup of its previous version
+    Restore,
+    /// Copy every expense of the ledger to another (empty) ledger, e.g. from CSV to SQLite
+    Migrate {
+        /// Ledger to copy the expenses to
+        #[arg(short, long)]
+        to: PathBuf,
+        /// Storage backend of the target ledger (detected from its extension by default)
+        #[arg(long, value_enum)]
+        to_backend: Option<Backend>,
+    },
+    /// Manage the local exchange-rate table used to convert expenses to the base currency
+    Rates {
+        #[command(subcommand)]
+        cmd: RateCommands,
+    },
+    /// Manage monthly budgets, overall or per category, and check how much of them is spent
+    Budget {
+        #[command(subcommand)]
+        cmd: BudgetCommands,
+    },
+    /// Manage the accounts records are paid from or received into
+    Account {
+        #[command(subcommand)]
+        cmd: AccountCommands,
+    },
+    /// Show the balance of every account, or the running balance of one account
+    Balance {
+        /// Show every record of this account with the balance after it
+        #[arg(short = 'a', long, value_parser = parse_category)]
+        account: Option<String>,
+    },
+    /// Compute who owes whom for the shared expenses, and the payments that settle every debt
+    Settle {
+        #[command(flatten)]
+        filter: Filter,
+    },
+    /// Import the records of a bank statement: a CSV export read with a saved profile, or an OFX/QFX or QIF file
+    Import {
+        /// The statement exported by the bank
+        statement: PathBuf,
+        /// Format of the statement (detected from the file extension by default: .ofx and .qfx are OFX, .qif is QIF, anything else CSV)
+        #[arg(long, value_enum)]
+        format: Option<StatementFormat>,
+        /// Name of the profile describing the columns of a CSV statement (see `profile add`)
+        #[arg(short = 'p', long)]
+        profile: Option<String>,
+        /// Account the imported records are assigned to (overrides the account of the profile)
+        #[arg(short = 'a', long, value_parser = parse_category)]
+        account: Option<String>,
+        /// Currency of statements that don't give one: QIF files, OFX files without CURDEF and CSV statements without
+        /// a currency column (overrides the currency of the profile). The base currency by default
+        #[arg(short = 'u', long, value_parser = parse_currency)]
+        currency: Option<String>,
+        /// Only show the records that would be imported
+        #[arg(long)]
+        dry_run: bool,
+        /// Import without asking for confirmation
+        #[arg(short = 'y', long, conflicts_with = "dry_run")]
+        yes: bool,
+        /// What to do with records that are already in the ledger (same date and amount, similar description)
+        #[arg(long, value_enum, default_value_t = OnDuplicate::Skip)]
+        on_duplicate: OnDuplicate,
+    },
+    /// Export records as the transactions of a QIF bank account, e.g. to move them to another personal-finance tool
+    Export {
+        /// File to write the QIF to (printed by default)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        filter: Filter,
+    },
+    /// Manage the profiles that describe the CSV exports of each bank
+    Profile {
+        #[command(subcommand)]
+        cmd: ProfileCommands,
+    },
+    /// Manage rules for expenses that repeat on a schedule (rent, subscriptions...) and create their expenses
+    Recurring {
+        #[command(subcommand)]
+        cmd: RecurringCommands,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum RateCommands {
+    /// Add a rate for a currency pair on a given date (replaces an existing rate for the same pair and date)
+    Add {
+        #[arg(short = 'f', long, value_parser = parse_currency)]
+        from: String,
+        /// Target currency (defaults to the base currency)
+        #[arg(short = 't', long, value_parser = parse_currency)]
+        to: Option<String>,
+        /// Units of the target currency bought by one unit of the source currency
+        #[arg(short = 'r', long)]
+        rate: Rate,
+        #[arg(short = 'd', long)]
+        date: Option<NaiveDate>,
+    },
+    List,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum BudgetCommands {
+    /// Set the monthly budget of a category, or of all expenses without --category (replaces an existing one)
+    Set {
+        /// Limit per month, in the base currency
+        #[arg(short = 'v', long)]
+        amount: Money,
+        #[arg(short = 'c', long, value_parser = parse_category)]
+        category: Option<String>,
+    },
+    List,
+    /// Remove the budget of a category, or the overall budget without --category
+    Remove {
+        #[arg(short = 'c', long, value_parser = parse_category)]
+        category: Option<String>,
+    },
+    /// Show how much of each budget is spent in a month (the current month by default)
+    Status {
+        #[arg(short = 'm', long, value_parser = clap::value_parser!(u32).range(1..=12))]
         month: Option<u32>,
+        #[arg(short = 'y', long, value_parser = clap::value_parser!(i32).range(1..=9999))]
+        year: Option<i32>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum AccountCommands {
+    Add {
+        #[arg(short = 'n', long, value_parser = parse_account)]
+        name: String,
+        /// Currency of the balance (defaults to the base currency)
+        #[arg(short = 'u', long, value_parser = parse_currency)]
+        currency: Option<String>,
+        /// Balance before the first record of the ledger
+        #[arg(short = 'o', long, default_value_t = Money::default())]
+        opening_balance: Money,
+    },
+    List,
+    /// Remove an account that no record uses anymore
+    Remove {
+        #[arg(short = 'n', long, value_parser = parse_account)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ProfileCommands {
+    /// Add a profile (replaces an existing profile with the same name)
+    Add {
+        #[command(flatten)]
+        profile: Box<Profile>,
+    },
+    List,
+    Remove {
+        #[arg(short = 'n', long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum RecurringCommands {
+    /// Add a rule (its expenses are only created by `recurring apply`)
+    Add {
+        #[arg(short = 'k', long)]
+        description: String,
+        #[arg(short = 'v', long)]
+        amount: Money,
+        #[arg(short = 'c', long, value_parser = parse_category)]
+        category: Option<String>,
+        /// Tag to attach to the expenses (can be repeated)
+        #[arg(short = 't', long = "tag", value_parser = parse_tag)]
+        tags: Vec<String>,
+        /// Currency of the amount (defaults to the base currency)
+        #[arg(short = 'u', long, value_parser = parse_currency)]
+        currency: Option<String>,
+        #[arg(short = 'f', long, value_enum)]
+        frequency: Frequency,
+        /// Number of days, weeks, months or years between two expenses, e.g. --frequency weekly --every 2
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        every: u32,
+        /// Date of the first expense (defaults to today)
+        #[arg(short = 's', long)]
+        start: Option<NaiveDate>,
+        /// Last day an expense may fall on
+        #[arg(short = 'e', long)]
+        end: Option<NaiveDate>,
+        /// What to do in months without the day of the start date, e.g. the 31st
+        #[arg(long, value_enum, default_value_t = ShortMonth::Clamp)]
+        short_month: ShortMonth,
+        /// Whether the rule creates expenses or income (e.g. a salary)
+        #[arg(long, value_enum, default_value_t = Kind::Expense)]
+        kind: Kind,
+        /// Account the expenses are paid from (or the income is received into)
+        #[arg(short = 'a', long, value_parser = parse_category)]
+        account: Option<String>,
+    },
+    List,
+    /// Remove a rule, keeping the expenses it already created
+    Remove {
+        #[arg(short, long)]
+        id: u32,
+    },
+    /// Create the expenses of every rule that are due up to today and were not created yet
+    Apply,
+}
+
+impl Commands {
+    /// Whether the command changes the ledger or one of its files, in which case it needs exclusive access
+    fn writes(&self) -> bool {
+        match self {
+            Commands::List { .. } | Commands::Summary { .. } | Commands::Stats { .. } | Commands::Migrate { .. } => false,
+            Commands::Doctor { quarantine, interactive } => *quarantine || *interactive,
+            Commands::Rates { cmd } => !matches!(cmd, RateCommands::List),
+            Commands::Budget { cmd } => matches!(cmd, BudgetCommands::Set { .. } | BudgetCommands::Remove { .. }),
+            Commands::Recurring { cmd } => !matches!(cmd, RecurringCommands::List),
+            Commands::Account { cmd } => !matches!(cmd, AccountCommands::List),
+            Commands::Balance { .. } | Commands::Settle { .. } | Commands::Export { .. } => false,
+            Commands::Import { dry_run, .. } => !dry_run,
+            Commands::Profile { cmd } => !matches!(cmd, ProfileCommands::List),
+            Commands::Add { .. } | Commands::Update { .. } | Commands::Delete { .. } | Commands::Restore => true,
+        }
+    }
+}
+
+/// Sorting and pagination of the List command
+#[derive(clap::Args, Debug, Clone)]
+struct Order {
+    /// Sort by this column (ties are broken by id)
+    #[arg(short = 's', long, value_enum)]
+    sort: Option<SortKey>,
+    /// Sort in descending order
+    #[arg(long, requires = "sort")]
+    desc: bool,
+    /// Newest expenses first (same as --sort date --desc)
+    #[arg(short = 'r', long, conflicts_with_all = ["sort", "desc"])]
+    reverse: bool,
+    /// Show at most this many expenses
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Skip this many expenses before showing any
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum SortKey {
+    Date,
+    /// In the currency of each expense, not converted
+    Amount,
+    Id,
+    Description,
+}
+
+/// Fields that can be changed by the Update command, all of them optional
+#[derive(clap::Args, Debug, Clone)]
+struct Changes {
+    #[arg(short = 'k', long)]
+    description: Option<String>,
+    #[arg(short = 'v', long)]
+    amount: Option<Money>,
+    #[arg(short = 'd', long)]
+    date: Option<NaiveDate>, 
+    /// New category (an empty string removes the category)
+    #[arg(short = 'c', long, value_parser = parse_category)]
+    category: Option<String>,
+    /// Tag to attach to the expense (can be repeated)
+    #[arg(short = 't', long = "tag", value_parser = parse_tag)]
+    tags: Vec<String>,
+    /// Tag to remove from the expense (can be repeated)
+    #[arg(long = "untag", value_parser = parse_tag)]
+    untags: Vec<String>,
+    #[arg(short = 'u', long, value_parser = parse_currency)]
+    currency: Option<String>,
+    #[arg(long, value_enum)]
+    kind: Option<Kind>,
+    /// New account (an empty string removes the account)
+    #[arg(short = 'a', long, value_parser = parse_category)]
+    account: Option<String>,
+    /// New target account of a transfer
+    #[arg(long, value_parser = parse_category)]
+    to_account: Option<String>,
+    #[arg(short = 'p', long, value_parser = parse_name)]
+    paid_by: Option<String>,
+    #[arg(long)]
+    split: Option<Split>,
+    /// Stop sharing the expense
+    #[arg(long, conflicts_with = "split")]
+    unsplit: bool,
+}
+
+/// Categories are case-insensitive, so they are stored trimmed and in lowercase
+fn parse_category(value: &str) -> Result<String, String> {
+    Ok(value.trim().to_lowercase())
+}
+
+/// Account names follow the same rules as categories, but can't be empty
+fn parse_account(value: &str) -> Result<String, String> {
+    let name = value.trim().to_lowercase();
+    if name.is_empty() {
+        return Err("Account names must be non-empty".into());
+    }
+    Ok(name)
+}
+
+/// Tags follow the same rules as categories, but can't be empty nor contain the `,` used to join them in the CSV file
+fn parse_tag(value: &str) -> Result<String, String> {
+    let tag = value.trim().to_lowercase();
+    if tag.is_empty() || tag.contains(',') {
+        return Err("Tags must be non-empty and cannot contain commas".into());
+    }
+    Ok(tag)
+}
+
+/// Tags are stored in a single column as a comma separated list, since the CSV reader/writer does not support sequences
+mod tag_list {
+    use std::collections::BTreeSet;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn join(tags: &BTreeSet<String>) -> String {
+        tags.iter().map(String::as_str).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn split(joined: &str) -> BTreeSet<String> {
+        joined.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(String::from).collect()
+    }
+
+    pub fn serialize<S: Serializer>(tags: &BTreeSet<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&join(tags))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeSet<String>, D::Error> {
+        let joined = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+        Ok(split(&joined))
+    }
+}
+
+/// Direction of the money of a record: spent (the default), received, or moved between two of the user's accounts
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    #[default]
+    Expense,
+    Income,
+    /// Neither spending nor income, only changes the balances of the accounts
+    Transfer,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Expense => "expense",
+            Kind::Income => "income",
+            Kind::Transfer => "transfer",
+        }
     }
 }
 
@@ -51,73 +508,160 @@ enum Commands {
 #[derive(Debug, Deserialize, Serialize)]
 struct Expense {
     id: u32, 
-    amount: f32, 
+    amount: Money, 
     description: String,
     date: NaiveDate,
+    /// Older files do not have this column, in which case the expense is uncategorized
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default, with = "tag_list")]
+    tags: BTreeSet<String>,
+    /// Older files do not have this column either; such expenses are assigned the base currency when loaded
+    #[serde(default)]
+    currency: String,
+    /// Identifies where an automatically created expense comes from (e.g. "rec:1:2025-01-31" for an occurrence
+    /// of recurring rule 1), so the same expense is never created twice
+    #[serde(default)]
+    reference: Option<String>,
+    /// Older files do not have this column, in which case every record is an expense
+    #[serde(default)]
+    kind: Kind,
+    /// Account the money leaves (or arrives to, for income). Older files do not have the account columns.
+    #[serde(default)]
+    account: Option<String>,
+    /// Account the money of a transfer arrives to
+    #[serde(default)]
+    to_account: Option<String>,
+    /// Person who paid an expense shared with others. Older files do not have the sharing columns.
+    #[serde(default)]
+    paid_by: Option<String>,
+    #[serde(default)]
+    split: Option<Split>,
 }
 
 impl Expense {
-    fn new(id: u32, description: String, amount: f32, date: Option<NaiveDate>) -> Self {
+    fn new(id: u32, description: String, amount: Money, date: Option<NaiveDate>, category: Option<String>, tags: Vec<String>, currency: String) -> Self {
         let date = date.unwrap_or(chrono::Local::now().date_naive()); 
-        Expense { id, description, amount, date }
+        let category = category.filter(|c| !c.is_empty());
+        let tags = tags.into_iter().collect();
+        Expense { id, description, amount, date, category, tags, currency, reference: None, kind: Kind::Expense, account: None, to_account: None, paid_by: None, split: None }
     }
-    fn update(&mut self, description: Option<String>, amount: Option<f32>, date: Option<NaiveDate>) {
-        if description.is_some() {
-            self.description = description.unwrap(); 
+    fn update(&mut self, changes: Changes) {
+        if let Some(description) = changes.description {
+            self.description = description; 
+        }
+        if let Some(amount) = changes.amount {
+            self.amount = amount;
         }
-        if amount.is_some() {
-            self.amount = amount.unwrap();
+        if let Some(date) = changes.date {
+            self.date = date; 
         }
-        if date.is_some() {
-            self.date = date.unwrap(); 
+        if let Some(category) = changes.category {
+            self.category = Some(category).filter(|c| !c.is_empty());
+        }
+        self.tags.extend(changes.tags);
+        for tag in changes.untags {
+            self.tags.remove(&tag);
+        }
+        if let Some(currency) = changes.currency {
+            self.currency = currency;
+        }
+        if let Some(kind) = changes.kind {
+            self.kind = kind;
+            if kind != Kind::Transfer {
+                self.to_account = None;
+            }
+        }
+        if let Some(account) = changes.account {
+            self.account = Some(account).filter(|a| !a.is_empty());
+        }
+        if let Some(to_account) = changes.to_account {
+            self.to_account = Some(to_account).filter(|a| !a.is_empty());
+        }
+        if let Some(paid_by) = changes.paid_by {
+            self.paid_by = Some(paid_by);
+        }
+        if let Some(split) = changes.split {
+            self.split = Some(split);
+        }
+        if changes.unsplit {
+            self.paid_by = None;
+            self.split = None;
         }
     }
+    fn category_name(&self) -> &str {
+        self.category.as_deref().unwrap_or(UNCATEGORIZED)
+    }
 }
 
+const UNCATEGORIZED: &str = "-";
+
 impl Display for Expense {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let date_str = self.date.format("%Y-%m-%d").to_string();
-        write!(f, "{:<3} | {:<10} | {:<10.2} | {}", self.id, date_str, self.amount, self.description)
+        // Income is marked with a + so it stands out from the expenses
+        let sign = if self.kind == Kind::Income { "+" } else { "" };
+        let amount_str = format!("{sign}{} {}", self.amount, self.currency);
+        write!(f, "{:<3} | {:<10} | {:<14} | {:<12} | {}", self.id, date_str, amount_str, self.category_name(), self.description)?;
+        match (&self.account, &self.to_account) {
+            (Some(from), Some(to)) => write!(f, " [{from} -> {to}]")?,
+            (Some(account), None) => write!(f, " [{account}]")?,
+            _ => {}
+        }
+        match (&self.paid_by, &self.split) {
+            (Some(paid_by), Some(split)) => write!(f, " (paid by {paid_by}, split {split})")?,
+            (Some(paid_by), None) => write!(f, " (paid by {paid_by})")?,
+            _ => {}
+        }
+        for tag in &self.tags {
+            write!(f, " #{tag}")?;
+        }
+        Ok(())
     }
 }
 
-const FILE_PATH: &'static str = "expenses.csv"; 
+const FILE_NAME: &str = "expenses.csv"; 
 
-fn create_db(file_path: &str) -> Result<(), std::io::Error> {
-    if !Path::new(file_path).exists() {
-        let mut file = File::create(file_path)?;
-        // Create a new CSV file with headers
-        let _ = file.write_all(b"id;date;description;amount");
+/// Ledger used when neither --file nor EXPENSE_TRACKER_FILE are given: $XDG_DATA_HOME/expense-tracker/expenses.csv on Linux
+fn default_file_path() -> Result<PathBuf, String> {
+    let data_dir = dirs::data_dir().ok_or("Could not determine the user's data directory, use --file to choose the ledger file")?;
+    let file_path = data_dir.join("expense-tracker").join(FILE_NAME);
+    // Older versions always used expenses.csv in the current directory. It keeps being used while it is there, with a
+    // reminder every time, since switching silently to a new empty ledger would make the expenses look lost.
+    if Path::new(FILE_NAME).exists() {
+        eprintln!("Note: using {FILE_NAME} in the current directory, where older versions kept the ledger; move it to {} to use it from anywhere", file_path.display());
+        return Ok(PathBuf::from(FILE_NAME));
     }
-    Ok(())
+    Ok(file_path)
 }
 
-/// Reads CSV file (columns separated by ; to avoid issues with different decimal separator (dot or comma)) using Serde for deserialization
-fn read_db(file_path: &str) -> Result<Vec<Expense>, csv::Error> {
-    let expenses = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .delimiter(b';')
-        .from_path(file_path)?
-        .deserialize::<Expense>()
-        .filter(|expense| expense.is_ok())
-        .map(|expense| expense.unwrap())
-        .collect();
+/// Names of the tables kept next to a ledger, which `migrate` copies along with it
+const SIDECARS: &[&str] = &["rates", "budgets", "accounts", "recurring", "profiles"];
 
-    Ok(expenses)
+/// Files that belong to a ledger live next to it and share its name, e.g. expenses.rates.csv for expenses.csv
+fn sidecar_path(file_path: &Path, name: &str) -> PathBuf {
+    let stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    file_path.with_file_name(format!("{stem}.{name}.csv"))
 }
 
-/// Writing entries to the CSV file using Serde for serialization
-fn write_db(file_path: &str, records: Vec<Expense>) -> Result<(), csv::Error> {
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(true)
-        .delimiter(b';')
-        .from_path(file_path)?; 
-
-    for record in records {
-        writer.serialize(record)?;
+/// Sorts the records and keeps only the requested page. Without --sort the order of the ledger is kept.
+fn sort_and_page(records: &mut Vec<Expense>, order: &Order) {
+    let (sort, desc) = if order.reverse { (Some(SortKey::Date), true) } else { (order.sort, order.desc) };
+    if let Some(sort) = sort {
+        records.sort_by(|a, b| {
+            let ordering = match sort {
+                SortKey::Date => a.date.cmp(&b.date),
+                SortKey::Amount => a.amount.cmp(&b.amount),
+                SortKey::Id => a.id.cmp(&b.id),
+                SortKey::Description => a.description.to_lowercase().cmp(&b.description.to_lowercase()),
+            }
+            .then(a.id.cmp(&b.id));
+            if desc { ordering.reverse() } else { ordering }
+        });
     }
-    writer.flush()?;
-    Ok(())
+    let end = order.limit.map_or(records.len(), |limit| order.offset.saturating_add(limit).min(records.len()));
+    records.truncate(end);
+    records.drain(..order.offset.min(records.len()));
 }
 
 fn print_db(records: &[Expense]) {
@@ -126,78 +670,611 @@ fn print_db(records: &[Expense]) {
         return; 
     }
     // Print headers + each entry
-    println!("{:<3} | {:<10} | {:<10} | {}", "ID", "Date", "Amount", "Description");
+    println!("ID  | Date       | Amount         | Category     | Description");
     for entry in records {
         println!("{}", entry);
     }
 }
 
-fn filter_records(records: &mut Vec<Expense>, month: Option<u32>) -> Result<(), String> {
-    let current_year = chrono::Local::now().year(); 
-    if let Some(month) = month {
-        if (1..=12).contains(&month) {
-            records.retain(|exp| exp.date.month() == month && exp.date.year() == current_year );
-        } else {
-            return Err("Invalid month (must be a number between 1 and 12)".into());
+/// Amounts of the expenses in the base currency, each converted with the rate of its own date
+fn convert_to_base(expenses: &[Expense], file_path: &Path, base_currency: &str) -> Result<Vec<Money>, Box<dyn Error>> {
+    let rates = RateTable::load(&sidecar_path(file_path, "rates"))?;
+    let converted = expenses.iter()
+        .map(|expense| rates.convert(expense.amount, &expense.currency, base_currency, expense.date))
+        .collect::<Result<Vec<Money>, String>>()?;
+    Ok(converted)
+}
+
+/// Totals of the expenses of a month, by category, in the base currency
+fn month_summary(storage: &mut dyn storage::Storage, file_path: &Path, base_currency: &str, filter: &Filter) -> Result<Vec<report::SummaryRow>, Box<dyn Error>> {
+    let mut expenses = storage.query(filter)?;
+    expenses.retain(|expense| expense.kind != Kind::Transfer);
+    let converted = convert_to_base(&expenses, file_path, base_currency)?;
+    Ok(report::summarize(&expenses, &converted, base_currency, true)?)
+}
+
+/// Warns when the month of a new expense is over the overall budget or the budget of the expense's category
+fn warn_over_budget(storage: &mut dyn storage::Storage, file_path: &Path, base_currency: &str, date: NaiveDate, category: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let budgets = BudgetTable::load(&sidecar_path(file_path, "budgets"))?;
+    if budgets.budgets.is_empty() {
+        return Ok(());
+    }
+    let filter = Filter::for_month(date.year(), date.month());
+    let summary = month_summary(storage, file_path, base_currency, &filter)?;
+    for status in budgets.status(&summary)? {
+        let applies = status.budget.category.is_none() || status.budget.category.as_deref() == category;
+        if applies && status.is_over() {
+            eprintln!(
+                "Warning: over the {} budget{}: spent {} of {} {base_currency} ({}%)",
+                status.budget.name(), filter.period_label(), status.spent, status.budget.amount, status.percent_used()
+            );
         }
     }
     Ok(())
 }
 
 pub fn run() -> Result<(), Box<dyn Error>> {
-    // Create the CSV file when the user first initializes the app, if one does not exist.
-    create_db(FILE_PATH)?;
-    // All operations, from reading to writing, require the current list of expenses stored. 
-    let mut expenses = read_db(FILE_PATH)?; 
     // Parsing commands 
-    let args = Args::parse().cmd;
-    match args {
-        Commands::Add { description, amount, date } => {
-            let id: u32 = if expenses.is_empty() {
-                1
-            } else {
-                expenses.iter().fold(1, |acc, expense| expense.id.max(acc)) + 1 
-            }; 
-            let new_expense = Expense::new(id, description, amount, date); 
-            expenses.push(new_expense); 
-            write_db(FILE_PATH, expenses)?;
-            println!("Successfully added new expense with ID {id}"); 
+    let Args { cmd, base_currency, file, backend, lock_timeout } = Args::parse();
+    let file_path = match file {
+        Some(file_path) => file_path,
+        None => default_file_path()?,
+    };
+    let backend = backend.unwrap_or_else(|| Backend::detect(&file_path));
+    // The lock file lives next to the ledger, so its directory must exist first
+    if let Some(parent) = file_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Held until the end of the command, so the whole read-modify-write cycle can't interleave with another invocation
+    let _lock = lock::acquire(&file_path, cmd.writes(), Duration::from_secs(lock_timeout))?;
+    // Creates the ledger when the user first initializes the app, if one does not exist.
+    let mut storage = storage::open(&file_path, backend, &base_currency)?;
+    match cmd {
+        Commands::Add { description, amount, date, category, tags, currency, kind, account, to_account, paid_by, split, on_duplicate } => {
+            // The storage assigns the id
+            let mut new_expense = Expense::new(0, description, amount, date, category, tags, currency.unwrap_or_else(|| base_currency.clone())); 
+            new_expense.kind = kind;
+            new_expense.account = account;
+            new_expense.to_account = to_account;
+            new_expense.paid_by = paid_by;
+            new_expense.split = split;
+            AccountTable::load(&sidecar_path(&file_path, "accounts"))?.check(&new_expense)?;
+            Split::check(&new_expense)?;
+            if on_duplicate != OnDuplicate::Keep {
+                // Duplicates are on the same date, so the rest of the ledger is not read
+                let existing = storage.on_date(new_expense.date)?;
+                if let Some(duplicate) = duplicate::find(&new_expense, &existing) {
+                    if !duplicate::keep(&new_expense, duplicate, on_duplicate)? {
+                        println!("The expense was not added");
+                        return Ok(());
+                    }
+                }
+            }
+            let date = new_expense.date;
+            let category = new_expense.category.clone();
+            let id = storage.insert(new_expense)?;
+            println!("Successfully added new {} with ID {id}", kind.name()); 
+            // The expense is already saved, so a budget that can't be checked (e.g. a missing exchange rate) is not an error
+            if kind == Kind::Expense {
+                if let Err(e) = warn_over_budget(storage.as_mut(), &file_path, &base_currency, date, category.as_deref()) {
+                    eprintln!("Warning: could not check the budgets: {e}");
+                }
+            }
         },
-        Commands::Update { id, description, amount , date} => {
-            if let Some(entry) = expenses.iter_mut().find(|expense| expense.id == id) {
-                entry.update(description, amount, date); 
-            } else {
+        Commands::Update { id, changes } => {
+            let Some(mut entry) = storage.get(id)? else {
                 return Err(format!("No entry found with ID = {}", id).into());
-            }
-            write_db(FILE_PATH, expenses)?;
+            };
+            entry.update(changes); 
+            AccountTable::load(&sidecar_path(&file_path, "accounts"))?.check(&entry)?;
+            Split::check(&entry)?;
+            storage.update(entry)?;
             println!("Sucessfully updated expense with ID {id}");  
         },
         Commands::Delete { id } => {
-            let previous_len = expenses.len();
-            expenses.retain(|x| x.id != id);
-            // Unequal lengths means the operation was successful 
-            if previous_len != expenses.len() { 
-                write_db(FILE_PATH, expenses)?; 
+            if storage.delete(id)? { 
                 println!("Successully deleted entry with ID {id}"); 
             } else {
                 return Err(format!("Expense with id = {} does not exist", id).into());
             }
         },
-        Commands::List { month } => {
-            // Filter according to month if necessary. 
-            filter_records(&mut expenses, month)?;
+        Commands::List { filter, order, format } => {
+            // Filter according to dates, category and tags if necessary. 
+            let mut expenses = storage.query(&filter)?;
+            let total_count = expenses.len();
+            sort_and_page(&mut expenses, &order);
+            if format != Format::Table {
+                let rows: Vec<ExpenseRow> = expenses.iter().map(ExpenseRow::from).collect();
+                return output::print_rows(&rows, format);
+            }
             print_db(&expenses); 
+            if !expenses.is_empty() && expenses.len() < total_count {
+                let first = order.offset + 1;
+                println!("(showing {first}-{} of {total_count})", order.offset + expenses.len());
+            }
         },
-        Commands::Summary {month} => {
-            filter_records(&mut expenses, month)?;
-            let total = expenses.iter().fold(0.0, |acc, expense| expense.amount + acc);
-            if let Some(month) = month {
-                let month_str = Month::from_u32(month).unwrap().name();
-                println!("Total expenses for {month_str}: {total}");
+        Commands::Summary { filter, by_category, group_by, format } => {
+            let mut expenses = storage.query(&filter)?;
+            if let Some(group_by) = group_by {
+                // Groups only show spending
+                expenses.retain(|expense| expense.kind == Kind::Expense);
+                let converted = convert_to_base(&expenses, &file_path, &base_currency)?;
+                let rows = report::group(&expenses, &converted, &base_currency, group_by)?;
+                if format == Format::Table {
+                    report::print_groups(&rows, group_by, Money::total(converted.iter().copied())?, &base_currency, &filter.period_label());
+                    return Ok(());
+                }
+                return output::print_rows(&rows, format);
+            }
+            // Transfers are in no total, and converting them would need rates for nothing
+            expenses.retain(|expense| expense.kind != Kind::Transfer);
+            let converted = convert_to_base(&expenses, &file_path, &base_currency)?;
+            let rows = report::summarize(&expenses, &converted, &base_currency, by_category)?;
+            if format == Format::Table {
+                report::print_summary(&rows, &base_currency, &filter.period_label());
             } else {
-                println!("Total expenses: {total}");
+                output::print_rows(&rows, format)?;
+            }
+        },
+        Commands::Stats { filter, outliers_by, outlier_factor } => {
+            let mut expenses = storage.query(&filter)?;
+            expenses.retain(|expense| expense.kind == Kind::Expense);
+            let converted = convert_to_base(&expenses, &file_path, &base_currency)?;
+            let Some(statistics) = Stats::compute(&converted) else {
+                println!("Nothing to analyze.");
+                return Ok(());
+            };
+            let outliers = stats::outliers(&expenses, &converted, outliers_by, outlier_factor);
+            stats::print(&statistics, &outliers, outliers_by, &base_currency, &filter.period_label());
+        },
+        Commands::Doctor { quarantine, interactive } => {
+            if backend != Backend::Csv {
+                return Err("doctor only applies to CSV ledgers".into());
+            }
+            doctor::run(&file_path, quarantine, interactive)?;
+        },
+        Commands::Restore => {
+            if backend != Backend::Csv {
+                return Err("restore only applies to CSV ledgers (SQLite ledgers are never left half-written)".into());
+            }
+            storage::csv::restore_db(&file_path)?;
+            println!("Successfully restored {} from its backup (run `restore` again to undo)", file_path.display());
+        },
+        Commands::Migrate { to, to_backend } => {
+            if to == file_path {
+                return Err("The target ledger must be a different file".into());
+            }
+            let to_backend = to_backend.unwrap_or_else(|| Backend::detect(&to));
+            let _target_lock = lock::acquire(&to, true, Duration::from_secs(lock_timeout))?;
+            let mut target = storage::open(&to, to_backend, &base_currency)?;
+            // Never merge into or overwrite an existing ledger
+            if !target.load()?.is_empty() {
+                return Err(format!("{} already has expenses, migrate only copies to an empty ledger", to.display()).into());
+            }
+            // The rates, budgets, rules... of the ledger are copied too, the same way never overwriting existing ones
+            let sidecars: Vec<(PathBuf, PathBuf)> = SIDECARS
+                .iter()
+                .map(|name| (sidecar_path(&file_path, name), sidecar_path(&to, name)))
+                .filter(|(from, _)| from.exists())
+                .collect();
+            if let Some((_, existing)) = sidecars.iter().find(|(_, to)| to.exists()) {
+                return Err(format!("{} already exists, migrate only copies to a new ledger", existing.display()).into());
+            }
+            let expenses = storage.load()?;
+            let count = expenses.len();
+            target.replace_all(expenses)?;
+            println!("Successfully copied {count} expense(s) to {}", to.display());
+            for (from, to) in sidecars {
+                std::fs::copy(&from, &to).map_err(|e| format!("Could not copy {} to {}: {e}", from.display(), to.display()))?;
+                println!("Copied {} to {}", from.display(), to.display());
+            }
+        },
+        Commands::Rates { cmd } => {
+            let rates_path = sidecar_path(&file_path, "rates");
+            let mut rates = RateTable::load(&rates_path)?;
+            match cmd {
+                RateCommands::Add { from, to, rate, date } => {
+                    let to = to.unwrap_or(base_currency);
+                    if from == to {
+                        return Err("The source and target currencies must be different".into());
+                    }
+                    let date = date.unwrap_or(chrono::Local::now().date_naive());
+                    let message = format!("Successfully added rate: 1 {from} = {rate} {to} on {date}");
+                    rates.set(ExchangeRate { date, from, to, rate });
+                    rates.save(&rates_path)?;
+                    println!("{message}");
+                },
+                RateCommands::List => {
+                    if rates.rates.is_empty() {
+                        println!("Nothing to list.");
+                    }
+                    for rate in &rates.rates {
+                        println!("{rate}");
+                    }
+                }
+            }
+        }
+        Commands::Budget { cmd } => {
+            let budgets_path = sidecar_path(&file_path, "budgets");
+            let mut budgets = BudgetTable::load(&budgets_path)?;
+            match cmd {
+                BudgetCommands::Set { amount, category } => {
+                    if amount <= Money::default() {
+                        return Err("The budget must be greater than zero".into());
+                    }
+                    let budget = Budget { category, amount };
+                    let message = format!("Successfully set the {} budget to {amount} {base_currency} per month", budget.name());
+                    budgets.set(budget);
+                    budgets.save(&budgets_path)?;
+                    println!("{message}");
+                },
+                BudgetCommands::List => {
+                    if budgets.budgets.is_empty() {
+                        println!("Nothing to list.");
+                    }
+                    for budget in &budgets.budgets {
+                        println!("{budget}");
+                    }
+                },
+                BudgetCommands::Remove { category } => {
+                    if !budgets.remove(category.as_deref()) {
+                        return Err(format!("No budget set for {}", category.as_deref().unwrap_or("all expenses")).into());
+                    }
+                    budgets.save(&budgets_path)?;
+                    println!("Successfully removed the budget");
+                },
+                BudgetCommands::Status { month, year } => {
+                    if budgets.budgets.is_empty() {
+                        println!("No budgets set (add one with `budget set --amount <AMOUNT>`).");
+                        return Ok(());
+                    }
+                    let today = chrono::Local::now().date_naive();
+                    let filter = Filter::for_month(year.unwrap_or(today.year()), month.unwrap_or(today.month()));
+                    let summary = month_summary(storage.as_mut(), &file_path, &base_currency, &filter)?;
+                    println!("Budgets{} (in {base_currency}):", filter.period_label());
+                    println!("Budget       | Limit      | Spent      | Remaining  | Used");
+                    for status in budgets.status(&summary)? {
+                        println!("{status}");
+                    }
+                },
+            }
+        }
+        Commands::Account { cmd } => {
+            let accounts_path = sidecar_path(&file_path, "accounts");
+            let mut accounts = AccountTable::load(&accounts_path)?;
+            match cmd {
+                AccountCommands::Add { name, currency, opening_balance } => {
+                    if accounts.get(&name).is_some() {
+                        return Err(format!("Account '{name}' already exists").into());
+                    }
+                    let currency = currency.unwrap_or(base_currency);
+                    println!("Successfully added account '{name}' with an opening balance of {opening_balance} {currency}");
+                    accounts.accounts.push(Account { name, currency, opening_balance });
+                    accounts.save(&accounts_path)?;
+                },
+                AccountCommands::List => {
+                    if accounts.accounts.is_empty() {
+                        println!("Nothing to list.");
+                        return Ok(());
+                    }
+                    println!("Account      | Currency | Opening balance");
+                    for account in &accounts.accounts {
+                        println!("{account}");
+                    }
+                },
+                AccountCommands::Remove { name } => {
+                    if accounts.get(&name).is_none() {
+                        return Err(format!("Account '{name}' does not exist").into());
+                    }
+                    // Records of a removed account would silently drop out of every balance
+                    let used = storage.load()?.iter().filter(|record| account::change(record, &name).is_some()).count();
+                    if used > 0 {
+                        return Err(format!("Account '{name}' is used by {used} record(s), move them to another account first").into());
+                    }
+                    accounts.accounts.retain(|account| account.name != name);
+                    accounts.save(&accounts_path)?;
+                    println!("Successfully removed account '{name}'");
+                },
+            }
+        }
+        Commands::Balance { account } => {
+            let accounts = AccountTable::load(&sidecar_path(&file_path, "accounts"))?;
+            let rates = RateTable::load(&sidecar_path(&file_path, "rates"))?;
+            let records = storage.load()?;
+            if let Some(name) = account {
+                let account = accounts.get(&name).ok_or_else(|| format!("Account '{name}' does not exist"))?;
+                println!("Date       | Change         | Balance        | Description");
+                println!("{:<10} | {:<14} | {:<14} | Opening balance", "", "", format!("{} {}", account.opening_balance, account.currency));
+                for movement in account::history(account, &records, &rates)? {
+                    let date_str = movement.record.date.format("%Y-%m-%d").to_string();
+                    let sign = if movement.change > Money::default() { "+" } else { "" };
+                    let change_str = format!("{sign}{} {}", movement.change, account.currency);
+                    let balance_str = format!("{} {}", movement.balance, account.currency);
+                    println!("{date_str:<10} | {change_str:<14} | {balance_str:<14} | {}", movement.record.description);
+                }
+                return Ok(());
+            }
+            if accounts.accounts.is_empty() {
+                println!("No accounts (add one with `account add --name <NAME>`).");
+                return Ok(());
+            }
+            println!("Account      | Balance            | Records");
+            for account in &accounts.accounts {
+                let history = account::history(account, &records, &rates)?;
+                let balance = history.last().map_or(account.opening_balance, |movement| movement.balance);
+                println!("{:<12} | {:<18} | {}", account.name, format!("{balance} {}", account.currency), history.len());
+            }
+        }
+        Commands::Settle { filter } => {
+            let mut records = storage.query(&filter)?;
+            // Only shared expenses are converted, so other records don't need exchange rates
+            records.retain(|record| record.paid_by.is_some() && record.split.is_some());
+            let converted = convert_to_base(&records, &file_path, &base_currency)?;
+            let balances = split::balances(&records, &converted)?;
+            if balances.values().all(|balance| *balance == Money::default()) {
+                println!("Nobody owes anything{}.", filter.period_label());
+                return Ok(());
+            }
+            println!("Balances{} (in {base_currency}, positive when owed money):", filter.period_label());
+            for (name, balance) in balances.iter().filter(|(_, balance)| **balance != Money::default()) {
+                println!("  {name:<12} {balance}");
+            }
+            println!("Payments to settle up:");
+            for payment in split::settle(&balances) {
+                println!("  {} pays {} {} {base_currency}", payment.from, payment.to, payment.amount);
+            }
+        }
+        Commands::Import { statement, format, profile, account, currency, dry_run, yes, on_duplicate } => {
+            let (mut records, skipped) = match format.unwrap_or_else(|| StatementFormat::detect(&statement)) {
+                StatementFormat::Csv => {
+                    let profile = profile.ok_or("Importing a CSV statement needs a --profile describing its columns")?;
+                    let profiles = ProfileTable::load(&sidecar_path(&file_path, "profiles"))?;
+                    let mut profile = profiles.get(&profile).ok_or_else(|| format!("Profile '{profile}' does not exist (add it with `profile add`)"))?.clone();
+                    profile.currency = currency.or(profile.currency);
+                    import::read_statement(&statement, &profile, &base_currency)?
+                }
+                StatementFormat::Ofx => ofx::read_statement(&statement, currency.as_deref().unwrap_or(&base_currency))?,
+                StatementFormat::Qif => qif::read_statement(&statement, currency.as_deref().unwrap_or(&base_currency), account.as_deref())?,
+            };
+            if account.is_some() {
+                // Transfers read from QIF files already have both of their accounts
+                for record in records.iter_mut().filter(|record| record.kind != Kind::Transfer) {
+                    record.account = account.clone();
+                }
+            }
+            let accounts = AccountTable::load(&sidecar_path(&file_path, "accounts"))?;
+            let existing = storage.load()?;
+            // Records with the reference of a record already in the ledger (e.g. the FITID of an OFX transaction) were imported before
+            let references: BTreeSet<&str> = existing.iter().filter_map(|expense| expense.reference.as_deref()).collect();
+            let count = records.len();
+            records.retain(|record| record.reference.as_deref().is_none_or(|reference| !references.contains(reference)));
+            if records.len() < count {
+                println!("Skipped {} record(s) imported before", count - records.len());
+            }
+            // A dry run never asks anything
+            let on_duplicate = if dry_run && on_duplicate == OnDuplicate::Ask { OnDuplicate::Warn } else { on_duplicate };
+            let mut new_records = Vec::new();
+            for record in records {
+                match duplicate::find(&record, &existing) {
+                    Some(duplicate) if !duplicate::keep(&record, duplicate, on_duplicate)? => {}
+                    _ => new_records.push(record),
+                }
+            }
+            let mut records = new_records;
+            // The preview shows the ids the records will get, since the ledger is locked until they are inserted
+            let next_id = storage.next_id()?;
+            for (record, id) in records.iter_mut().zip(next_id..) {
+                record.id = id;
+            }
+            for record in &records {
+                accounts.check(record)?;
+            }
+            for row in &skipped {
+                eprintln!("Warning: skipping line {} of {}: {}", row.line, statement.display(), row.error);
+            }
+            if records.is_empty() {
+                println!("Nothing to import.");
+                return Ok(());
+            }
+            println!("ID  | Date       | Amount         | Category     | Description");
+            for record in &records {
+                println!("{record}");
+            }
+            if dry_run {
+                println!("{} record(s) would be imported (dry run, nothing was changed)", records.len());
+                return Ok(());
+            }
+            if !yes {
+                let answer = doctor::prompt(&format!("Import {} record(s)? [y/N] ", records.len()))?;
+                if !matches!(answer.as_deref(), Some("y" | "Y" | "yes")) {
+                    println!("Nothing was imported.");
+                    return Ok(());
+                }
+            }
+            let count = records.len();
+            for record in records {
+                storage.insert(record)?;
+            }
+            println!("Successfully imported {count} record(s)");
+        }
+        Commands::Export { output, filter } => {
+            let mut records = storage.query(&filter)?;
+            // QIF has no currencies, so mixing them would silently add up amounts of different currencies
+            let currencies: BTreeSet<&str> = records.iter().map(|expense| expense.currency.as_str()).collect();
+            if currencies.len() > 1 {
+                let currencies: Vec<&str> = currencies.into_iter().collect();
+                return Err(format!(
+                    "QIF has no currencies, and these records are in {}: export one currency at a time (e.g. --where \"currency = {}\")",
+                    currencies.join(", "), currencies[0]
+                ).into());
+            }
+            records.sort_by_key(|expense| (expense.date, expense.id));
+            let qif = qif::write(&records);
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, qif).map_err(|e| format!("Could not write {}: {e}", output.display()))?;
+                    println!("Successfully exported {} record(s) to {}", records.len(), output.display());
+                }
+                None => print!("{qif}"),
+            }
+        }
+        Commands::Profile { cmd } => {
+            let profiles_path = sidecar_path(&file_path, "profiles");
+            let mut profiles = ProfileTable::load(&profiles_path)?;
+            match cmd {
+                ProfileCommands::Add { profile } => {
+                    let message = format!("Successfully saved profile '{}'", profile.name);
+                    profiles.set(*profile);
+                    profiles.save(&profiles_path)?;
+                    println!("{message}");
+                },
+                ProfileCommands::List => {
+                    if profiles.profiles.is_empty() {
+                        println!("Nothing to list.");
+                    }
+                    for profile in &profiles.profiles {
+                        println!("{profile}");
+                    }
+                },
+                ProfileCommands::Remove { name } => {
+                    if !profiles.remove(&name) {
+                        return Err(format!("Profile '{name}' does not exist").into());
+                    }
+                    profiles.save(&profiles_path)?;
+                    println!("Successfully removed profile '{name}'");
+                },
+            }
+        }
+        Commands::Recurring { cmd } => {
+            let rules_path = sidecar_path(&file_path, "recurring");
+            let mut rules = RuleTable::load(&rules_path)?;
+            match cmd {
+                RecurringCommands::Add { description, amount, category, tags, currency, frequency, every, start, end, short_month, kind, account } => {
+                    let start = start.unwrap_or(chrono::Local::now().date_naive());
+                    if kind == Kind::Transfer {
+                        return Err("Recurring rules can only create expenses or income".into());
+                    }
+                    if let Some(account) = &account {
+                        if AccountTable::load(&sidecar_path(&file_path, "accounts"))?.get(account).is_none() {
+                            return Err(format!("Unknown account '{account}' (add it with `account add --name {account}`)").into());
+                        }
+                    }
+                    if end.is_some_and(|end| end < start) {
+                        return Err(format!("--end must not be before the start date ({start})").into());
+                    }
+                    // Ids of removed rules are not reused while the ledger still has their expenses,
+                    // otherwise their references could be mistaken for the new rule's
+                    let used_ids = storage.load()?.into_iter().filter_map(|expense| expense.reference.as_deref().and_then(recurring::rule_id));
+                    let id = rules.rules.iter().map(|rule| rule.id).chain(used_ids).fold(0, u32::max) + 1;
+                    rules.rules.push(Rule {
+                        id,
+                        description,
+                        amount,
+                        currency: currency.unwrap_or(base_currency),
+                        category: category.filter(|c| !c.is_empty()),
+                        tags: tags.into_iter().collect(),
+                        frequency,
+                        interval: every,
+                        start,
+                        end,
+                        short_month,
+                        applied_until: None,
+                        kind,
+                        account,
+                    });
+                    rules.save(&rules_path)?;
+                    println!("Successfully added recurring rule with ID {id} (run `recurring apply` to create its expenses)");
+                },
+                RecurringCommands::List => {
+                    if rules.rules.is_empty() {
+                        println!("Nothing to list.");
+                        return Ok(());
+                    }
+                    println!("ID  | Schedule                                 | Amount         | Category     | Description");
+                    for rule in &rules.rules {
+                        println!("{rule}");
+                    }
+                },
+                RecurringCommands::Remove { id } => {
+                    if !rules.remove(id) {
+                        return Err(format!("Recurring rule with id = {} does not exist", id).into());
+                    }
+                    rules.save(&rules_path)?;
+                    println!("Successully removed recurring rule with ID {id} (its expenses are kept)");
+                },
+                RecurringCommands::Apply => {
+                    let today = chrono::Local::now().date_naive();
+                    // References of the expenses created before, in case a previous run stopped before saving the rules
+                    let existing: BTreeSet<String> = storage.load()?.into_iter().filter_map(|expense| expense.reference).collect();
+                    let mut created = 0;
+                    for rule in &mut rules.rules {
+                        for date in rule.due(today) {
+                            if existing.contains(&rule.reference(date)) {
+                                continue;
+                            }
+                            let id = storage.insert(rule.to_expense(date))?;
+                            println!("Added expense with ID {id}: {} on {date}", rule.description);
+                            created += 1;
+                        }
+                        rule.applied_until = Some(today);
+                    }
+                    rules.save(&rules_path)?;
+                    println!("Successfully created {created} recurring expense(s)");
+                },
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Expense> {
+        [(1, "coffee", "3.50", 10), (2, "Bread", "2.00", 12), (3, "apples", "3.50", 11), (4, "rent", "900", 10)]
+            .into_iter()
+            .map(|(id, description, amount, day)| {
+                Expense::new(id, description.into(), amount.parse().unwrap(), NaiveDate::from_ymd_opt(2025, 1, day), None, Vec::new(), "USD".into())
+            })
+            .collect()
+    }
+
+    fn ids(order: Order) -> Vec<u32> {
+        let mut records = records();
+        sort_and_page(&mut records, &order);
+        records.iter().map(|record| record.id).collect()
+    }
+
+    fn order(sort: Option<SortKey>, desc: bool) -> Order {
+        Order { sort, desc, reverse: false, limit: None, offset: 0 }
+    }
+
+    #[test]
+    fn keeps_the_ledger_order_without_sort() {
+        assert_eq!(ids(order(None, false)), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn sorts_with_ties_broken_by_id() {
+        assert_eq!(ids(order(Some(SortKey::Date), false)), [1, 4, 3, 2]);
+        assert_eq!(ids(order(Some(SortKey::Amount), false)), [2, 1, 3, 4]);
+        // Descending reverses the ties too
+        assert_eq!(ids(order(Some(SortKey::Amount), true)), [4, 3, 1, 2]);
+        assert_eq!(ids(Order { reverse: true, ..order(None, false) }), [2, 3, 4, 1]);
+        // Descriptions ignore case
+        assert_eq!(ids(order(Some(SortKey::Description), false)), [3, 2, 1, 4]);
+        assert_eq!(ids(order(Some(SortKey::Id), true)), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn pages_after_sorting() {
+        let page = |limit, offset| ids(Order { limit, offset, ..order(Some(SortKey::Id), false) });
+        assert_eq!(page(Some(2), 0), [1, 2]);
+        assert_eq!(page(Some(2), 3), [4]);
+        assert_eq!(page(None, 1), [2, 3, 4]);
+        assert_eq!(page(Some(2), 10), Vec::<u32>::new());
+        assert_eq!(page(Some(0), 0), Vec::<u32>::new());
+        assert_eq!(page(Some(usize::MAX), 1), [2, 3, 4]);
+    }
+}
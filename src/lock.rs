@@ -0,0 +1,45 @@
+use std::{fs::{File, OpenOptions}, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
+use fs4::fs_std::FileExt;
+
+/// How often a busy lock is tried again while waiting
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Advisory lock on a ledger, released when dropped (or when the process exits, even if it crashes).
+/// The lock is taken on a separate .lock file because the ledger itself is replaced on every write.
+pub struct LedgerLock {
+    _file: File,
+}
+
+pub fn lock_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    file_path.with_file_name(name)
+}
+
+/// Takes the lock of a ledger, exclusive for commands that change it and shared for the ones that only read it,
+/// waiting up to `timeout` for other invocations to finish.
+pub fn acquire(file_path: &Path, exclusive: bool, timeout: Duration) -> Result<LedgerLock, String> {
+    let lock_path = lock_path(file_path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format!("Could not open lock file {}: {e}", lock_path.display()))?;
+    let started = Instant::now();
+    loop {
+        // Called through the trait explicitly, newer versions of std have inherent methods with the same names
+        let locked = if exclusive { FileExt::try_lock_exclusive(&file) } else { FileExt::try_lock_shared(&file) };
+        match locked {
+            Ok(true) => return Ok(LedgerLock { _file: file }),
+            Ok(false) if started.elapsed() < timeout => thread::sleep(RETRY_INTERVAL),
+            Ok(false) => {
+                return Err(format!(
+                    "Could not lock {} after waiting {}s: another expense-tracker command is using it (use --lock-timeout to wait longer)",
+                    file_path.display(), timeout.as_secs()
+                ))
+            }
+            Err(e) => return Err(format!("Could not lock {}: {e}", lock_path.display())),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::{fmt::Display, ops::{Add, AddAssign, Neg, Sub, SubAssign}, str::FromStr};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of decimal places kept by `Money`
+const MONEY_SCALE: u32 = 2;
+/// Number of decimal places kept by `Rate`
+const RATE_SCALE: u32 = 6;
+
+/// Exact amount of money, stored as an integer number of cents so sums never drift like floats do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+    pub fn cents(self) -> i64 {
+        self.0
+    }
+    /// Converts the amount with an exchange rate, rounding half away from zero to the nearest cent.
+    /// None when the converted amount is too large to be represented.
+    pub fn convert(self, rate: Rate) -> Option<Money> {
+        i64::try_from(div_round(self.0 as i128 * rate.0 as i128, 10i128.pow(RATE_SCALE))).ok().map(Money)
+    }
+    /// Inverse of `convert`, used when only the opposite direction of a currency pair is known
+    pub fn convert_inverse(self, rate: Rate) -> Option<Money> {
+        i64::try_from(div_round(self.0 as i128 * 10i128.pow(RATE_SCALE), rate.0 as i128)).ok().map(Money)
+    }
+    /// Adds the amounts, or fails when the result doesn't fit (plain `+` would panic or wrap around)
+    pub fn checked_add(self, rhs: Money) -> Result<Money, String> {
+        self.0.checked_add(rhs.0).map(Money).ok_or_else(too_large)
+    }
+    pub fn checked_sub(self, rhs: Money) -> Result<Money, String> {
+        self.0.checked_sub(rhs.0).map(Money).ok_or_else(too_large)
+    }
+    /// Sum of the amounts, used for every total of the reports
+    pub fn total<I: IntoIterator<Item = Money>>(amounts: I) -> Result<Money, String> {
+        amounts.into_iter().try_fold(Money::default(), Money::checked_add)
+    }
+    /// Splits the amount in `parts` equal parts (e.g. for averages), rounding half away from zero to the nearest cent
+    pub fn divide(self, parts: usize) -> Money {
+        // Never larger than the amount itself, so it always fits
+        Money(div_round(self.0 as i128, parts.max(1) as i128) as i64)
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_fixed(s, MONEY_SCALE).map(Money).map_err(|e| format!("Invalid amount '{s}': {e}"))
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `pad` so that width and alignment flags (e.g. {:<10}) keep working in tables
+        f.pad(&format_fixed(self.0, MONEY_SCALE))
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+/// Amounts are written as decimal strings (e.g. "12.30") so the CSV file stays human-readable and exact
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Also accepts the float representation written by older versions (e.g. "3000.0" or "1.5e3"), as long as the
+/// amount fits in cents (up to about 92 quadrillion); larger values such as "1e20" are rejected as too large
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Exchange rate with six decimal places: how many units of the target currency one unit of the source currency buys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate(i64);
+
+impl FromStr for Rate {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_fixed(s, RATE_SCALE) {
+            Ok(rate) if rate > 0 => Ok(Rate(rate)),
+            Ok(_) => Err(format!("Invalid rate '{s}': must be greater than zero")),
+            Err(e) => Err(format!("Invalid rate '{s}': {e}")),
+        }
+    }
+}
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format_fixed(self.0, RATE_SCALE))
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn too_large() -> String {
+    format!("Amounts too large to add up (the largest total is {})", Money(i64::MAX))
+}
+
+/// Integer division rounding half away from zero (the divisor is always positive here)
+fn div_round(dividend: i128, divisor: i128) -> i128 {
+    let (quotient, remainder) = (dividend / divisor, dividend % divisor);
+    if remainder.abs() >= divisor - remainder.abs() { quotient + dividend.signum() } else { quotient }
+}
+
+/// Parses a decimal number into an integer scaled by 10^scale, rounding half away from zero.
+/// Both `.` and `,` are accepted as decimal separator, as well as an exponent (`1.5e3`).
+pub(crate) fn parse_fixed(s: &str, scale: u32) -> Result<i64, String> {
+    let s = s.trim();
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(pos) => {
+            let exponent: i32 = unsigned[pos + 1..].parse().map_err(|_| "invalid exponent".to_string())?;
+            (&unsigned[..pos], exponent)
+        }
+        None => (unsigned, 0),
+    };
+    let (int_part, frac_part) = match mantissa.find(['.', ',']) {
+        Some(pos) => (&mantissa[..pos], &mantissa[pos + 1..]),
+        None => (mantissa, ""),
+    };
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err("not a number".into());
+    }
+    if !int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit()) {
+        return Err("not a number".into());
+    }
+
+    let too_large = || "number too large".to_string();
+    let mut digits: i128 = 0;
+    for c in int_part.chars().chain(frac_part.chars()) {
+        digits = digits.checked_mul(10).and_then(|d| d.checked_add((c as u8 - b'0') as i128)).ok_or_else(too_large)?;
+    }
+    // value = digits * 10^(exponent - frac_len), scaled value = value * 10^scale
+    let shift = exponent as i64 - frac_part.len() as i64 + scale as i64;
+    let scaled = if shift >= 0 {
+        let factor = 10i128.checked_pow(shift as u32).ok_or_else(too_large)?;
+        digits.checked_mul(factor).ok_or_else(too_large)?
+    } else if -shift > 38 {
+        0
+    } else {
+        let divisor = 10i128.pow((-shift) as u32);
+        let (quotient, remainder) = (digits / divisor, digits % divisor);
+        if remainder >= divisor - remainder { quotient + 1 } else { quotient }
+    };
+    let scaled = i64::try_from(scaled).map_err(|_| too_large())?;
+    Ok(if negative { -scaled } else { scaled })
+}
+
+/// Formats an integer scaled by 10^scale as a decimal string with exactly `scale` decimal places
+pub(crate) fn format_fixed(value: i64, scale: u32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let value = value.unsigned_abs();
+    let divisor = 10u64.pow(scale);
+    if scale == 0 {
+        return format!("{sign}{value}");
+    }
+    format!("{sign}{}.{:0width$}", value / divisor, value % divisor, width = scale as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cents(s: &str) -> i64 {
+        s.parse::<Money>().unwrap().cents()
+    }
+
+    #[test]
+    fn parses_decimal_amounts() {
+        assert_eq!(cents("12.30"), 1230);
+        assert_eq!(cents("12,3"), 1230);
+        assert_eq!(cents("7"), 700);
+        assert_eq!(cents(".5"), 50);
+        assert_eq!(cents("+1.00"), 100);
+        assert_eq!(cents("1.5e3"), 150000);
+        assert_eq!(cents("3000.0"), 300000);
+    }
+
+    #[test]
+    fn rounds_half_away_from_zero() {
+        assert_eq!(cents("0.005"), 1);
+        assert_eq!(cents("0.0049"), 0);
+        assert_eq!(cents("-0.005"), -1);
+        assert_eq!(cents("-2.675"), -268);
+        assert_eq!(cents("1e-9"), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_amounts() {
+        for invalid in ["", "-", ".", "abc", "1.2.3", "--1", "1e", "1e20", "92233720368547759"] {
+            assert!(invalid.parse::<Money>().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn formats_with_two_decimals() {
+        assert_eq!(Money::from_cents(1230).to_string(), "12.30");
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05");
+        assert_eq!(format!("{:>8}", Money::from_cents(100)), "    1.00");
+    }
+
+    #[test]
+    fn converts_with_rates() {
+        let rate: Rate = "0.25".parse().unwrap();
+        assert_eq!(Money::from_cents(1002).convert(rate), Some(Money::from_cents(251)));
+        assert_eq!(Money::from_cents(-1002).convert(rate), Some(Money::from_cents(-251)));
+        assert_eq!(Money::from_cents(251).convert_inverse(rate), Some(Money::from_cents(1004)));
+        let huge: Rate = "1000000".parse().unwrap();
+        assert_eq!(Money::from_cents(i64::MAX / 10).convert(huge), None);
+        assert!("0".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn totals_fail_instead_of_overflowing() {
+        assert_eq!(Money::total([Money::from_cents(1), Money::from_cents(2)]), Ok(Money::from_cents(3)));
+        assert!(Money::total([Money::from_cents(i64::MAX), Money::from_cents(1)]).is_err());
+        assert_eq!(Money::from_cents(100).divide(3), Money::from_cents(33));
+        assert_eq!(Money::from_cents(-5).divide(2), Money::from_cents(-3));
+    }
+}
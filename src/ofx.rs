@@ -0,0 +1,171 @@
+use std::{error::Error, path::Path};
+use chrono::NaiveDate;
+use crate::{import::SkippedRow, money::Money, Expense, Kind};
+
+/// Reads the transactions (`<STMTTRN>`) of every statement of an OFX or QFX file. Both the SGML format of OFX 1.x, where values
+/// have no closing tags, and the XML format of OFX 2.x are read. The FITID of each transaction is kept in the
+/// reference of its record as "ofx:<account id>:<fitid>", so the same transaction is never imported twice.
+pub fn read_statement(file_path: &Path, default_currency: &str) -> Result<(Vec<Expense>, Vec<SkippedRow>), Box<dyn Error>> {
+    let bytes = std::fs::read(file_path).map_err(|e| format!("Could not read {}: {e}", file_path.display()))?;
+    let content = String::from_utf8_lossy(&bytes);
+    if !content.contains("<OFX>") {
+        return Err(format!("{} is not an OFX file", file_path.display()).into());
+    }
+    let (mut records, mut skipped) = (Vec::new(), Vec::new());
+    for (offset, statement) in statements(&content) {
+        let account = value(statement, "ACCTID").unwrap_or_default();
+        let currency = value(statement, "CURDEF").unwrap_or(default_currency).to_uppercase();
+        let mut rest = statement;
+        while let Some(start) = rest.find("<STMTTRN>") {
+            let block = &rest[start + "<STMTTRN>".len()..];
+            let end = block.find("</STMTTRN>").or_else(|| block.find("<STMTTRN>")).unwrap_or(block.len());
+            let transaction = &block[..end];
+            let line = content[..offset + statement.len() - rest.len() + start].matches('\n').count() as u64 + 1;
+            match read_transaction(transaction, account, &currency) {
+                Ok(record) => records.push(record),
+                Err(error) => skipped.push(SkippedRow { line, error }),
+            }
+            rest = &block[end..];
+        }
+    }
+    Ok((records, skipped))
+}
+
+/// The statements of the file with their offsets: `<STMTRS>` for bank accounts and `<CCSTMTRS>` for credit cards.
+/// A file can have several, each with its own account and currency. Files without any are read as a single statement.
+fn statements(content: &str) -> Vec<(usize, &str)> {
+    let mut starts: Vec<usize> = content.match_indices("<STMTRS>").chain(content.match_indices("<CCSTMTRS>")).map(|(start, _)| start).collect();
+    starts.sort_unstable();
+    if starts.is_empty() {
+        starts.push(0);
+    }
+    // Each statement ends where the next one starts, so a missing closing tag can't merge two of them
+    let ends = starts.iter().skip(1).copied().chain([content.len()]);
+    starts.iter().zip(ends).map(|(&start, end)| (start, &content[start..end])).collect()
+}
+
+fn read_transaction(transaction: &str, account: &str, currency: &str) -> Result<Expense, String> {
+    let fitid = value(transaction, "FITID").ok_or("transaction without FITID")?;
+    let posted = value(transaction, "DTPOSTED").ok_or("transaction without DTPOSTED")?;
+    // Dates are YYYYMMDD, optionally followed by a time and a time zone (e.g. 20250131120000.000[-5:EST])
+    let date = posted.get(..8).and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok()).ok_or_else(|| format!("'{posted}' is not a date"))?;
+    let amount_str = value(transaction, "TRNAMT").ok_or("transaction without TRNAMT")?;
+    let amount: Money = amount_str.parse()?;
+    let name = value(transaction, "NAME").map(unescape);
+    let memo = value(transaction, "MEMO").map(unescape);
+    let description = match (name, memo) {
+        (Some(name), Some(memo)) if !memo.is_empty() && memo != name => format!("{name} - {memo}"),
+        (Some(name), _) => name,
+        (None, Some(memo)) => memo,
+        (None, None) => value(transaction, "TRNTYPE").unwrap_or("transaction").to_lowercase(),
+    };
+    let mut record = Expense::new(0, description, Money::from_cents(amount.cents().abs()), Some(date), None, Vec::new(), currency.to_string());
+    // Money leaving the account is negative, like in most bank statements
+    if amount > Money::default() {
+        record.kind = Kind::Income;
+    }
+    record.reference = Some(format!("ofx:{account}:{fitid}"));
+    Ok(record)
+}
+
+/// Value of the first `<TAG>` of the text: everything up to the next tag, since OFX 1.x does not close them
+fn value<'a>(text: &'a str, tag: &str) -> Option<&'a str> {
+    let start = text.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let rest = &text[start..];
+    let end = rest.find('<').unwrap_or(rest.len());
+    Some(rest[..end].trim())
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&nbsp;", " ").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(ofx: &str) -> (Vec<Expense>, Vec<SkippedRow>) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), ofx).unwrap();
+        read_statement(file.path(), "EUR").unwrap()
+    }
+
+    fn summary(records: &[Expense]) -> Vec<(String, i64, Kind, String, String)> {
+        records
+            .iter()
+            .map(|record| (record.date.to_string(), record.amount.cents(), record.kind, record.currency.clone(), record.reference.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    const SGML: &str = "OFXHEADER:100\nDATA:OFXSGML\nVERSION:102\n\n<OFX>\n<BANKMSGSRSV1><STMTTRNRS><STMTRS>\n<CURDEF>USD\n\
+        <BANKACCTFROM><BANKID>123<ACCTID>0001<ACCTTYPE>CHECKING</BANKACCTFROM>\n<BANKTRANLIST>\n\
+        <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20250103120000.000[-5:EST]<TRNAMT>-12.50<FITID>A1<NAME>Cafe &amp; Bar<MEMO>Lunch</STMTTRN>\n\
+        <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20250131<TRNAMT>3000.00<FITID>A2<NAME>Employer</STMTTRN>\n\
+        </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1>\n</OFX>\n";
+
+    #[test]
+    fn reads_sgml_and_xml_the_same_way() {
+        let xml = "<?xml version=\"1.0\"?>\n<?OFX OFXHEADER=\"200\" VERSION=\"220\"?>\n<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>\n\
+            <CURDEF>USD</CURDEF>\n<BANKACCTFROM><BANKID>123</BANKID><ACCTID>0001</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>\n<BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20250103</DTPOSTED><TRNAMT>-12.50</TRNAMT><FITID>A1</FITID><NAME>Cafe &amp; Bar</NAME><MEMO>Lunch</MEMO></STMTTRN>\n\
+            <STMTTRN><TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>20250131</DTPOSTED><TRNAMT>3000.00</TRNAMT><FITID>A2</FITID><NAME>Employer</NAME></STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>\n";
+        let (sgml_records, _) = read(SGML);
+        let (xml_records, _) = read(xml);
+        assert_eq!(
+            summary(&sgml_records),
+            [
+                ("2025-01-03".to_string(), 1250, Kind::Expense, "USD".to_string(), "ofx:0001:A1".to_string()),
+                ("2025-01-31".to_string(), 300000, Kind::Income, "USD".to_string(), "ofx:0001:A2".to_string()),
+            ]
+        );
+        assert_eq!(summary(&xml_records), summary(&sgml_records));
+        assert_eq!(sgml_records[0].description, "Cafe & Bar - Lunch");
+        assert_eq!(xml_records[0].description, "Cafe & Bar - Lunch");
+    }
+
+    #[test]
+    fn reads_the_account_and_currency_of_each_statement() {
+        let ofx = "<OFX>\n<BANKMSGSRSV1><STMTTRNRS><STMTRS><CURDEF>USD<BANKACCTFROM><ACCTID>0001</BANKACCTFROM>\n\
+            <BANKTRANLIST><STMTTRN><DTPOSTED>20250103<TRNAMT>-12.50<FITID>1</STMTTRN></BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1>\n\
+            <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS><CURDEF>GBP<CCACCTFROM><ACCTID>4111</CCACCTFROM>\n\
+            <BANKTRANLIST><STMTTRN><DTPOSTED>20250104<TRNAMT>-20.00<FITID>1</STMTTRN></BANKTRANLIST></CCSTMTRS>\n\
+            <CCSTMTRS><CCACCTFROM><ACCTID>5500</CCACCTFROM>\n\
+            <BANKTRANLIST><STMTTRN><DTPOSTED>20250105<TRNAMT>-5.00<FITID>1</STMTTRN></BANKTRANLIST></CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>\n</OFX>\n";
+        let (records, skipped) = read(ofx);
+        assert!(skipped.is_empty());
+        // The last statement has no CURDEF, so it is in the default currency
+        assert_eq!(
+            summary(&records),
+            [
+                ("2025-01-03".to_string(), 1250, Kind::Expense, "USD".to_string(), "ofx:0001:1".to_string()),
+                ("2025-01-04".to_string(), 2000, Kind::Expense, "GBP".to_string(), "ofx:4111:1".to_string()),
+                ("2025-01-05".to_string(), 500, Kind::Expense, "EUR".to_string(), "ofx:5500:1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn references_are_the_same_on_every_import() {
+        let (first, _) = read(SGML);
+        let (second, _) = read(SGML);
+        let references: Vec<_> = first.iter().map(|record| record.reference.clone()).collect();
+        assert_eq!(references, second.iter().map(|record| record.reference.clone()).collect::<Vec<_>>());
+        assert_eq!(references, [Some("ofx:0001:A1".to_string()), Some("ofx:0001:A2".to_string())]);
+    }
+
+    #[test]
+    fn skips_transactions_that_cannot_be_read() {
+        let ofx = SGML.replace("<TRNAMT>3000.00", "<TRNAMT>lots");
+        let (records, skipped) = read(&ofx);
+        assert_eq!(records.len(), 1);
+        assert_eq!(skipped.iter().map(|row| row.line).collect::<Vec<_>>(), [11]);
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_ofx() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "Date,Amount\n").unwrap();
+        assert!(read_statement(file.path(), "EUR").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::{error::Error, io::{self, Write}};
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Output formats of List and Summary. `table` is the human readable format, the others are meant for scripts.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Table,
+    /// A JSON array of objects
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Comma separated values with a header line
+    Csv,
+    /// Tab separated values with a header line
+    Tsv,
+    /// A Markdown table
+    Markdown,
+}
+
+/// A row of machine-readable output: serialized as an object for JSON, and as `values` under `COLUMNS` otherwise
+pub trait Row: Serialize {
+    const COLUMNS: &'static [&'static str];
+    fn values(&self) -> Vec<String>;
+}
+
+/// Prints rows in one of the machine-readable formats (the table format is printed by each command itself)
+pub fn print_rows<T: Row>(rows: &[T], format: Format) -> Result<(), Box<dyn Error>> {
+    match write_rows(&mut io::stdout().lock(), rows, format) {
+        // The output was piped into a command that stopped reading early (e.g. `| head`), which is not an error
+        Err(error) if is_broken_pipe(error.as_ref()) => Ok(()),
+        result => result,
+    }
+}
+
+fn write_rows<T: Row>(out: &mut impl Write, rows: &[T], format: Format) -> Result<(), Box<dyn Error>> {
+    match format {
+        // Commands print their own table, JSON is the closest fallback
+        Format::Table | Format::Json => writeln!(out, "{}", serde_json::to_string_pretty(rows)?)?,
+        Format::Jsonl => {
+            for row in rows {
+                writeln!(out, "{}", serde_json::to_string(row)?)?;
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(&mut *out);
+            writer.write_record(T::COLUMNS)?;
+            for row in rows {
+                writer.write_record(row.values())?;
+            }
+            writer.flush()?;
+        }
+        Format::Markdown => {
+            writeln!(out, "| {} |", T::COLUMNS.join(" | "))?;
+            writeln!(out, "|{}", "---|".repeat(T::COLUMNS.len()))?;
+            for row in rows {
+                // Pipes would end the cell early
+                let values: Vec<String> = row.values().iter().map(|value| value.replace('|', "\\|")).collect();
+                writeln!(out, "| {} |", values.join(" | "))?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    let io_error = match error.downcast_ref::<csv::Error>() {
+        Some(error) => match error.kind() {
+            csv::ErrorKind::Io(error) => Some(error),
+            _ => None,
+        },
+        None => error.downcast_ref::<io::Error>(),
+    };
+    io_error.is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe)
+}
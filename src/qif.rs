@@ -0,0 +1,250 @@
+use std::{error::Error, path::Path};
+use chrono::{Datelike, NaiveDate};
+use crate::{import::{self, SkippedRow}, money::Money, parse_account, Expense, Kind};
+
+/// Account types of the QIF sections holding bank transactions. Other sections (investments, category and class
+/// lists, memorized transactions) are skipped.
+const TRANSACTION_TYPES: &[&str] = &["bank", "cash", "ccard", "oth a", "oth l"];
+
+/// Reads the bank transactions of a QIF file. QIF has no currencies, so every record gets `currency`.
+/// Records are assigned to `account`, which transfers to or from another account (`L[Savings]`) need.
+pub fn read_statement(file_path: &Path, currency: &str, account: Option<&str>) -> Result<(Vec<Expense>, Vec<SkippedRow>), Box<dyn Error>> {
+    let bytes = std::fs::read(file_path).map_err(|e| format!("Could not read {}: {e}", file_path.display()))?;
+    let content = String::from_utf8_lossy(&bytes);
+    if !content.trim_start().starts_with('!') {
+        return Err(format!("{} is not a QIF file (it should start with a header such as !Type:Bank)", file_path.display()).into());
+    }
+    let (mut records, mut skipped) = (Vec::new(), Vec::new());
+    let mut in_transactions = false;
+    let mut fields: Vec<(char, &str)> = Vec::new();
+    let mut first_line = 1;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim_end();
+        if let Some(header) = line.strip_prefix('!') {
+            // `!Option:` and `!Clear:` lines only change how Quicken reads the file
+            if let Some(account_type) = header.strip_prefix("Type:") {
+                in_transactions = TRANSACTION_TYPES.contains(&account_type.trim().to_lowercase().as_str());
+            } else if header.eq_ignore_ascii_case("Account") {
+                in_transactions = false;
+            }
+            fields.clear();
+            continue;
+        }
+        let mut chars = line.chars();
+        match chars.next() {
+            None => {}
+            Some('^') => {
+                if in_transactions && !fields.is_empty() {
+                    match read_transaction(&fields, currency, account) {
+                        Ok(record) => records.push(record),
+                        Err(error) => skipped.push(SkippedRow { line: first_line, error }),
+                    }
+                }
+                fields.clear();
+            }
+            Some(code) => {
+                if fields.is_empty() {
+                    first_line = number as u64 + 1;
+                }
+                fields.push((code, chars.as_str().trim()));
+            }
+        }
+    }
+    Ok((records, skipped))
+}
+
+fn read_transaction(fields: &[(char, &str)], currency: &str, account: Option<&str>) -> Result<Expense, String> {
+    let field = |code: char| fields.iter().find(|(c, _)| *c == code).map(|(_, value)| *value).filter(|value| !value.is_empty());
+    let date = parse_date(field('D').ok_or("transaction without date (D)")?)?;
+    // `U` is the same amount with more precision, written by newer versions of Quicken
+    let amount_str = field('T').or_else(|| field('U')).ok_or("transaction without amount (T)")?;
+    let amount = import::parse_amount(amount_str, decimal_separator(amount_str))?;
+    let description = match (field('P'), field('M')) {
+        (Some(payee), Some(memo)) if memo != payee => format!("{payee} - {memo}"),
+        (Some(payee), _) => payee.to_string(),
+        (None, Some(memo)) => memo.to_string(),
+        (None, None) => "transaction".to_string(),
+    };
+    // Categories may be followed by a class ("Food:Groceries/Vacation"), which has no counterpart in the ledger
+    let category = field('L').map(|category| category.split('/').next().unwrap_or_default().trim());
+    // Transfers name the other account instead of a category ("[Savings]")
+    let other_account = category.and_then(|category| category.strip_prefix('[')?.strip_suffix(']'));
+    let category = category.filter(|_| other_account.is_none()).map(str::to_lowercase);
+    let mut record = Expense::new(0, description, Money::from_cents(amount.cents().abs()), Some(date), category, Vec::new(), currency.to_string());
+    record.account = account.map(str::to_string);
+    if let Some(other_account) = other_account {
+        let account = account.ok_or_else(|| format!("transfer with [{other_account}], import it with --account <NAME> (the account of the file)"))?;
+        let other_account = parse_account(other_account)?;
+        record.kind = Kind::Transfer;
+        // Money leaving the account of the file goes to the other one, and money arriving comes from it
+        (record.account, record.to_account) = if amount > Money::default() {
+            (Some(other_account), Some(account.to_string()))
+        } else {
+            (Some(account.to_string()), Some(other_account))
+        };
+    } else if amount > Money::default() {
+        record.kind = Kind::Income;
+    }
+    Ok(record)
+}
+
+/// QIF dates are month first (01/31/2025, 1/31/25, or 1/31'25 for years from 2000 in older Quicken files).
+/// Dates with dots are day first (31.01.2025), and ISO dates (2025-01-31) are accepted too.
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    let invalid = || format!("'{value}' is not a date");
+    let compact: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Ok(date) = NaiveDate::parse_from_str(&compact, "%Y-%m-%d") {
+        return Ok(date);
+    }
+    let parts: Vec<&str> = compact.split(['/', '\'', '.', '-']).collect();
+    let [first, second, year] = parts[..] else {
+        return Err(invalid());
+    };
+    let (month, day) = if compact.contains('.') { (second, first) } else { (first, second) };
+    let (month, day, mut year): (u32, u32, i32) = match (month.parse(), day.parse(), year.parse()) {
+        (Ok(month), Ok(day), Ok(year)) => (month, day, year),
+        _ => return Err(invalid()),
+    };
+    if year < 100 {
+        year += if compact.contains('\'') || year < 70 { 2000 } else { 1900 };
+    }
+    NaiveDate::from_ymd_opt(year, month, day).ok_or_else(invalid)
+}
+
+/// `.` unless the amount only has a comma followed by one or two digits (12,50), which is a decimal comma.
+/// With both, the last one is the decimal separator (1.234,50).
+fn decimal_separator(amount: &str) -> char {
+    match (amount.rfind('.'), amount.rfind(',')) {
+        (Some(dot), Some(comma)) if comma > dot => ',',
+        (None, Some(comma)) if (1..=2).contains(&(amount.len() - comma - 1)) => ',',
+        _ => '.',
+    }
+}
+
+/// Writes the records as the transactions of a QIF bank account. Expenses and transfers are negative amounts,
+/// and transfers name the account they go to as their category, as Quicken does.
+pub fn write(records: &[Expense]) -> String {
+    let mut qif = String::from("!Type:Bank\n");
+    for record in records {
+        let date = record.date;
+        let amount = if record.kind == Kind::Income { record.amount } else { -record.amount };
+        qif.push_str(&format!("D{:02}/{:02}/{}\nT{amount}\nP{}\n", date.month(), date.day(), date.year(), record.description));
+        match (&record.to_account, &record.category) {
+            (Some(to_account), _) if record.kind == Kind::Transfer => qif.push_str(&format!("L[{to_account}]\n")),
+            (_, Some(category)) => qif.push_str(&format!("L{category}\n")),
+            _ => {}
+        }
+        qif.push_str("^\n");
+    }
+    qif
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn reads_month_first_dates() {
+        assert_eq!(parse_date("01/31/2025"), Ok(date(2025, 1, 31)));
+        assert_eq!(parse_date(" 2/ 5/2025"), Ok(date(2025, 2, 5)));
+        assert_eq!(parse_date("2025-01-31"), Ok(date(2025, 1, 31)));
+        assert!(parse_date("31/01/2025").is_err());
+        assert!(parse_date("1/31").is_err());
+    }
+
+    #[test]
+    fn reads_two_digit_years() {
+        assert_eq!(parse_date("1/31/25"), Ok(date(2025, 1, 31)));
+        assert_eq!(parse_date("1/31/69"), Ok(date(2069, 1, 31)));
+        assert_eq!(parse_date("1/31/70"), Ok(date(1970, 1, 31)));
+        assert_eq!(parse_date("12/31/99"), Ok(date(1999, 12, 31)));
+        // Quicken's apostrophe always means a year from 2000
+        assert_eq!(parse_date("1/31'25"), Ok(date(2025, 1, 31)));
+        assert_eq!(parse_date("1/31' 5"), Ok(date(2005, 1, 31)));
+    }
+
+    #[test]
+    fn reads_dates_with_dots_day_first() {
+        assert_eq!(parse_date("31.01.2025"), Ok(date(2025, 1, 31)));
+        assert_eq!(parse_date("5.2.25"), Ok(date(2025, 2, 5)));
+        assert!(parse_date("01.31.2025").is_err());
+    }
+
+    #[test]
+    fn guesses_decimal_separators() {
+        assert_eq!(decimal_separator("-1,234.56"), '.');
+        assert_eq!(decimal_separator("-1.234,56"), ',');
+        assert_eq!(decimal_separator("12,5"), ',');
+        assert_eq!(decimal_separator("12,50"), ',');
+        assert_eq!(decimal_separator("1,234"), '.');
+        assert_eq!(decimal_separator("1234"), '.');
+    }
+
+    #[test]
+    fn reads_transactions_of_bank_sections_only() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let qif = "!Account\nNChecking\n^\n!Type:Bank\nD1/31'25\nT-1,234.56\nPSupermarket\nMWeekly shop\nLFood:Groceries/Vacation\n^\n\
+                   D2/1/25\nT3,000.00\nPEmployer\nL[Savings]\n^\nD13/45/2025\nT-5\n^\n!Type:Cat\nNFood\n^\n";
+        std::fs::write(file.path(), qif).unwrap();
+        let (records, skipped) = read_statement(file.path(), "EUR", Some("checking")).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].amount, records[0].kind), (Money::from_cents(123456), Kind::Expense));
+        assert_eq!(records[0].description, "Supermarket - Weekly shop");
+        assert_eq!(records[0].category.as_deref(), Some("food:groceries"));
+        assert_eq!((records[1].kind, records[1].category.as_deref(), records[1].currency.as_str()), (Kind::Transfer, None, "EUR"));
+        // Money arriving from the savings account
+        assert_eq!((records[1].account.as_deref(), records[1].to_account.as_deref()), (Some("savings"), Some("checking")));
+        assert_eq!(skipped.iter().map(|row| row.line).collect::<Vec<_>>(), [16]);
+
+        // Without the account of the file, transfers can't be imported
+        let (records, skipped) = read_statement(file.path(), "EUR", None).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(skipped.iter().map(|row| row.line).collect::<Vec<_>>(), [11, 16]);
+        assert!(skipped[0].error.contains("--account"));
+    }
+
+    #[test]
+    fn exports_signed_amounts_and_transfers() {
+        let mut lunch = Expense::new(1, "Lunch".into(), Money::from_cents(1250), Some(date(2025, 1, 3)), Some("food".into()), Vec::new(), "USD".into());
+        let mut salary = Expense::new(2, "Salary".into(), Money::from_cents(300000), Some(date(2025, 1, 31)), None, Vec::new(), "USD".into());
+        salary.kind = Kind::Income;
+        let mut savings = Expense::new(3, "Savings".into(), Money::from_cents(5000), Some(date(2025, 2, 1)), Some("ignored".into()), Vec::new(), "USD".into());
+        savings.kind = Kind::Transfer;
+        savings.to_account = Some("savings".into());
+        lunch.tags.insert("work".into());
+        assert_eq!(
+            write(&[lunch, salary, savings]),
+            "!Type:Bank\nD01/03/2025\nT-12.50\nPLunch\nLfood\n^\nD01/31/2025\nT3000.00\nPSalary\n^\nD02/01/2025\nT-50.00\nPSavings\nL[savings]\n^\n"
+        );
+    }
+
+    #[test]
+    fn exported_records_are_imported_back() {
+        let mut lunch = Expense::new(1, "Lunch".into(), Money::from_cents(1250), Some(date(2025, 1, 3)), Some("food:restaurants".into()), Vec::new(), "USD".into());
+        lunch.account = Some("checking".into());
+        let mut salary = Expense::new(2, "Salary".into(), Money::from_cents(300000), Some(date(2025, 1, 31)), None, Vec::new(), "USD".into());
+        salary.kind = Kind::Income;
+        salary.account = Some("checking".into());
+        let mut savings = Expense::new(3, "Savings".into(), Money::from_cents(5000), Some(date(2025, 2, 1)), None, Vec::new(), "USD".into());
+        savings.kind = Kind::Transfer;
+        savings.account = Some("checking".into());
+        savings.to_account = Some("savings".into());
+        let exported = [lunch, salary, savings];
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), write(&exported)).unwrap();
+        let (imported, skipped) = read_statement(file.path(), "USD", Some("checking")).unwrap();
+        assert!(skipped.is_empty());
+        let fields = |record: &Expense| {
+            (
+                record.date, record.amount, record.kind, record.description.clone(), record.category.clone(),
+                record.currency.clone(), record.account.clone(), record.to_account.clone(),
+            )
+        };
+        assert_eq!(imported.iter().map(fields).collect::<Vec<_>>(), exported.iter().map(fields).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,345 @@
+use std::{cmp::Ordering, str::FromStr};
+use chrono::NaiveDate;
+use regex::Regex;
+use crate::{money::Money, Expense};
+
+/// Condition over a single expense, built from the --where option and from the other filter options.
+///
+/// Grammar (keywords and field names are case-insensitive):
+/// ```text
+/// query      := and ("or" and)*
+/// and        := unary ("and" unary)*
+/// unary      := "not" unary | "(" query ")" | comparison
+/// comparison := field operator value
+/// field      := id | date | amount | description | category | tag | currency | kind | account
+/// operator   := = | != | < | <= | > | >= | ~ (contains) | !~ (does not contain) | =~ (matches regex)
+/// ```
+/// Values containing spaces or operators must be quoted with `'` or `"`.
+#[derive(Debug, Clone)]
+pub enum Query {
+    All,
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Id(Comparison, u32),
+    Date(Comparison, NaiveDate),
+    /// Compared in the currency of each expense, not converted
+    Amount(Comparison, Money),
+    Text(TextField, TextTest),
+    /// True when any of the tags passes the test
+    Tag(TextTest),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextField {
+    Description,
+    /// Uncategorized expenses have an empty category
+    Category,
+    Currency,
+    /// "expense", "income" or "transfer"
+    Kind,
+    /// Account the money leaves (or arrives to, for income), empty when there is none
+    Account,
+}
+
+/// Text comparisons ignore case, except for regular expressions (which can use `(?i)`)
+#[derive(Debug, Clone)]
+pub enum TextTest {
+    Equals(String),
+    Contains(String),
+    Matches(Regex),
+}
+
+impl TextTest {
+    fn passes(&self, text: &str) -> bool {
+        match self {
+            TextTest::Equals(value) => text.to_lowercase() == *value,
+            TextTest::Contains(value) => text.to_lowercase().contains(value.as_str()),
+            TextTest::Matches(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl Query {
+    pub fn and(self, other: Query) -> Query {
+        match (self, other) {
+            (Query::All, query) | (query, Query::All) => query,
+            (left, right) => Query::And(Box::new(left), Box::new(right)),
+        }
+    }
+
+    pub fn matches(&self, expense: &Expense) -> bool {
+        match self {
+            Query::All => true,
+            Query::And(left, right) => left.matches(expense) && right.matches(expense),
+            Query::Or(left, right) => left.matches(expense) || right.matches(expense),
+            Query::Not(query) => !query.matches(expense),
+            Query::Id(comparison, id) => comparison.holds(expense.id.cmp(id)),
+            Query::Date(comparison, date) => comparison.holds(expense.date.cmp(date)),
+            Query::Amount(comparison, amount) => comparison.holds(expense.amount.cmp(amount)),
+            Query::Text(field, test) => test.passes(match field {
+                TextField::Description => &expense.description,
+                TextField::Category => expense.category.as_deref().unwrap_or_default(),
+                TextField::Currency => &expense.currency,
+                TextField::Kind => expense.kind.name(),
+                TextField::Account => expense.account.as_deref().unwrap_or_default(),
+            }),
+            Query::Tag(test) => expense.tags.iter().any(|tag| test.passes(tag)),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let query = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(format!("Invalid query: unexpected {token}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{word}'"),
+            Token::Quoted(text) => write!(f, "\"{text}\""),
+            Token::Operator(operator) => write!(f, "'{operator}'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+/// Longest operators first, so that e.g. `<=` is not read as `<` followed by `=`
+const OPERATORS: &[&str] = &["!=", "<=", ">=", "!~", "=~", "==", "=", "<", ">", "~"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '\'' || c == '"' {
+            let end = rest[1..].find(c).ok_or_else(|| format!("Invalid query: missing closing {c}"))?;
+            tokens.push(Token::Quoted(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()'\"!=<>~".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("Invalid query: unexpected '{c}'"));
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_and()?;
+        while self.next_is_keyword("or") {
+            self.position += 1;
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_unary()?;
+        while self.next_is_keyword("and") {
+            self.position += 1;
+            query = Query::And(Box::new(query), Box::new(self.parse_unary()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, String> {
+        if self.next_is_keyword("not") {
+            self.position += 1;
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("Invalid query: missing closing parenthesis".into()),
+                }
+            }
+            Some(Token::Word(field)) => self.parse_comparison(&field),
+            Some(token) => Err(format!("Invalid query: expected a field name but found {token}")),
+            None => Err("Invalid query: unexpected end of the query".into()),
+        }
+    }
+
+    fn parse_comparison(&mut self, field: &str) -> Result<Query, String> {
+        let operator = match self.next() {
+            Some(Token::Operator(operator)) => operator,
+            Some(token) => return Err(format!("Invalid query: expected an operator after '{field}' but found {token}")),
+            None => return Err(format!("Invalid query: expected an operator after '{field}'")),
+        };
+        let value = match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+            _ => return Err(format!("Invalid query: expected a value after '{field} {operator}'")),
+        };
+        let field = field.to_lowercase();
+        match field.as_str() {
+            "id" => Ok(Query::Id(comparison(&field, operator)?, value.parse().map_err(|_| format!("Invalid query: '{value}' is not an id"))?)),
+            "date" => {
+                let date = NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| format!("Invalid query: '{value}' is not a date (%Y-%m-%d)"))?;
+                Ok(Query::Date(comparison(&field, operator)?, date))
+            }
+            "amount" => Ok(Query::Amount(comparison(&field, operator)?, value.parse()?)),
+            "description" | "desc" => text_query(operator, &value, |test| Query::Text(TextField::Description, test)),
+            "category" => text_query(operator, &value, |test| Query::Text(TextField::Category, test)),
+            "currency" => text_query(operator, &value, |test| Query::Text(TextField::Currency, test)),
+            "tag" | "tags" => text_query(operator, &value, Query::Tag),
+            "kind" => text_query(operator, &value, |test| Query::Text(TextField::Kind, test)),
+            "account" => text_query(operator, &value, |test| Query::Text(TextField::Account, test)),
+            _ => Err(format!("Invalid query: unknown field '{field}' (expected id, date, amount, description, category, tag, currency, kind or account)")),
+        }
+    }
+}
+
+fn comparison(field: &str, operator: &str) -> Result<Comparison, String> {
+    match operator {
+        "=" | "==" => Ok(Comparison::Eq),
+        "!=" => Ok(Comparison::Ne),
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        ">" => Ok(Comparison::Gt),
+        ">=" => Ok(Comparison::Ge),
+        _ => Err(format!("Invalid query: operator '{operator}' can't be used with {field}")),
+    }
+}
+
+fn text_query(operator: &str, value: &str, build: impl Fn(TextTest) -> Query) -> Result<Query, String> {
+    let value_lowercase = value.to_lowercase();
+    match operator {
+        "=" | "==" => Ok(build(TextTest::Equals(value_lowercase))),
+        "!=" => Ok(Query::Not(Box::new(build(TextTest::Equals(value_lowercase))))),
+        "~" => Ok(build(TextTest::Contains(value_lowercase))),
+        "!~" => Ok(Query::Not(Box::new(build(TextTest::Contains(value_lowercase))))),
+        "=~" => {
+            let regex = Regex::new(value).map_err(|e| format!("Invalid query: bad regular expression '{value}': {e}"))?;
+            Ok(build(TextTest::Matches(regex)))
+        }
+        _ => Err(format!("Invalid query: operator '{operator}' can only be used with id, date and amount")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expense(amount: &str, category: &str, tags: &[&str]) -> Expense {
+        let tags = tags.iter().map(|tag| tag.to_string()).collect();
+        Expense::new(1, "Lunch at work".into(), amount.parse().unwrap(), NaiveDate::from_ymd_opt(2025, 1, 15), Some(category.into()), tags, "USD".into())
+    }
+
+    fn matches(query: &str, expense: &Expense) -> bool {
+        query.parse::<Query>().unwrap().matches(expense)
+    }
+
+    #[test]
+    fn tokenizes_longest_operators_first() {
+        let word = |word: &str| Token::Word(word.into());
+        assert_eq!(tokenize("amount>=10").unwrap(), [word("amount"), Token::Operator(">="), word("10")]);
+        assert_eq!(tokenize("desc !~ 'a b'").unwrap(), [word("desc"), Token::Operator("!~"), Token::Quoted("a b".into())]);
+        assert_eq!(tokenize("(tag=x)").unwrap(), [Token::Open, word("tag"), Token::Operator("="), word("x"), Token::Close]);
+        assert!(tokenize("desc = 'open").is_err());
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let food = expense("5", "food", &[]);
+        // Read as `category = food or (amount > 100 and category = rent)`
+        assert!(matches("category = food or amount > 100 and category = rent", &food));
+        assert!(!matches("(category = food or amount > 100) and category = rent", &food));
+        assert!(matches("not category = rent and amount < 10", &food));
+    }
+
+    #[test]
+    fn compares_fields() {
+        let lunch = expense("12.50", "Food", &["work"]);
+        assert!(matches("amount = 12.5", &lunch));
+        assert!(matches("date >= 2025-01-01 and date < 2025-02-01", &lunch));
+        assert!(matches("category = FOOD", &lunch));
+        assert!(matches("description ~ work", &lunch));
+        assert!(matches("tag = work and not tag = trip", &lunch));
+        assert!(matches("description =~ '^Lunch'", &lunch));
+        assert!(!matches("description =~ '^lunch'", &lunch));
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        assert!("color = red".parse::<Query>().is_err());
+        assert!("amount ~ 10".parse::<Query>().is_err());
+        assert!("(amount > 10".parse::<Query>().is_err());
+        assert!("amount > 10 category = food".parse::<Query>().is_err());
+        assert!("date = yesterday".parse::<Query>().is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::{collections::BTreeSet, fmt::Display, path::Path};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use crate::{atomic, money::Money, tag_list, Expense, Kind};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// What happens in the months that don't have the day of the start date (e.g. the 31st, or February 29th)
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortMonth {
+    /// The expense falls on the last day of the month instead
+    #[default]
+    Clamp,
+    /// There is no expense that month
+    Skip,
+}
+
+/// A rule that creates the same expense on a regular schedule, starting on `start`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Rule {
+    pub id: u32,
+    pub description: String,
+    pub amount: Money,
+    pub currency: String,
+    pub category: Option<String>,
+    #[serde(default, with = "tag_list")]
+    pub tags: BTreeSet<String>,
+    pub frequency: Frequency,
+    /// Number of days, weeks, months or years between two occurrences
+    pub interval: u32,
+    pub start: NaiveDate,
+    /// Last day an occurrence may fall on, if any
+    pub end: Option<NaiveDate>,
+    #[serde(default)]
+    pub short_month: ShortMonth,
+    /// Day up to which the occurrences were already created by `recurring apply`
+    pub applied_until: Option<NaiveDate>,
+    /// Older files do not have this column, in which case the rule creates expenses
+    #[serde(default)]
+    pub kind: Kind,
+    #[serde(default)]
+    pub account: Option<String>,
+}
+
+impl Rule {
+    /// Date of the n-th occurrence (the first one is the start date), clamped to the end of shorter months
+    fn occurrence(&self, n: u32) -> Option<NaiveDate> {
+        let steps = n.checked_mul(self.interval)?;
+        match self.frequency {
+            Frequency::Daily => self.start.checked_add_days(Days::new(steps as u64)),
+            Frequency::Weekly => self.start.checked_add_days(Days::new(steps as u64 * 7)),
+            // Adding months already clamps to the last day of shorter months
+            Frequency::Monthly => self.start.checked_add_months(Months::new(steps)),
+            Frequency::Yearly => self.start.checked_add_months(Months::new(steps.checked_mul(12)?)),
+        }
+    }
+
+    /// Dates of the occurrences that were not applied yet, up to `until` (inclusive)
+    pub fn due(&self, until: NaiveDate) -> Vec<NaiveDate> {
+        let last = self.end.map_or(until, |end| end.min(until));
+        let mut dates = Vec::new();
+        for n in 0.. {
+            let Some(date) = self.occurrence(n).filter(|date| *date <= last) else {
+                break;
+            };
+            if self.short_month == ShortMonth::Skip && date.day() != self.start.day() {
+                continue;
+            }
+            if self.applied_until.is_none_or(|applied| date > applied) {
+                dates.push(date);
+            }
+        }
+        dates
+    }
+
+    /// Reference of the expense created for the occurrence on `date`
+    pub fn reference(&self, date: NaiveDate) -> String {
+        format!("rec:{}:{}", self.id, date.format("%Y-%m-%d"))
+    }
+
+    pub fn to_expense(&self, date: NaiveDate) -> Expense {
+        let mut expense = Expense::new(
+            0, self.description.clone(), self.amount, Some(date), self.category.clone(), self.tags.iter().cloned().collect(), self.currency.clone(),
+        );
+        expense.reference = Some(self.reference(date));
+        expense.kind = self.kind;
+        expense.account = self.account.clone();
+        expense
+    }
+
+    fn schedule(&self) -> String {
+        let unit = match self.frequency {
+            Frequency::Daily => "day",
+            Frequency::Weekly => "week",
+            Frequency::Monthly => "month",
+            Frequency::Yearly => "year",
+        };
+        let mut schedule = match self.interval {
+            1 => format!("every {unit} from {}", self.start),
+            interval => format!("every {interval} {unit}s from {}", self.start),
+        };
+        if let Some(end) = self.end {
+            schedule.push_str(&format!(" to {end}"));
+        }
+        schedule
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.kind == Kind::Income { "+" } else { "" };
+        let amount_str = format!("{sign}{} {}", self.amount, self.currency);
+        let category = self.category.as_deref().unwrap_or(crate::UNCATEGORIZED);
+        write!(f, "{:<3} | {:<40} | {:<14} | {:<12} | {}", self.id, self.schedule(), amount_str, category, self.description)?;
+        for tag in &self.tags {
+            write!(f, " #{tag}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rule id of a reference created by `Rule::reference`
+pub fn rule_id(reference: &str) -> Option<u32> {
+    reference.strip_prefix("rec:")?.split(':').next()?.parse().ok()
+}
+
+/// Recurring rules, kept in the `.recurring.csv` file next to the ledger.
+pub struct RuleTable {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleTable {
+    pub fn load(file_path: &Path) -> Result<Self, csv::Error> {
+        Ok(RuleTable { rules: atomic::load_table(file_path)? })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<(), csv::Error> {
+        atomic::save_table(file_path, &self.rules)
+    }
+
+    /// Whether there was a rule with this id. The expenses it already created are kept.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let count = self.rules.len();
+        self.rules.retain(|rule| rule.id != id);
+        self.rules.len() != count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    fn rule(frequency: Frequency, interval: u32, start: &str) -> Rule {
+        Rule {
+            id: 7,
+            description: "Rent".to_string(),
+            amount: Money::from_cents(100000),
+            currency: "USD".to_string(),
+            category: None,
+            tags: BTreeSet::new(),
+            frequency,
+            interval,
+            start: date(start),
+            end: None,
+            short_month: ShortMonth::Clamp,
+            applied_until: None,
+            kind: Kind::Expense,
+            account: None,
+        }
+    }
+
+    fn due(rule: &Rule, until: &str) -> Vec<String> {
+        rule.due(date(until)).into_iter().map(|date| date.to_string()).collect()
+    }
+
+    #[test]
+    fn every_interval_steps_from_the_start_date() {
+        assert_eq!(due(&rule(Frequency::Daily, 3, "2025-01-01"), "2025-01-10"), ["2025-01-01", "2025-01-04", "2025-01-07", "2025-01-10"]);
+        assert_eq!(due(&rule(Frequency::Weekly, 2, "2025-01-01"), "2025-02-01"), ["2025-01-01", "2025-01-15", "2025-01-29"]);
+        assert_eq!(due(&rule(Frequency::Monthly, 2, "2025-01-15"), "2025-07-14"), ["2025-01-15", "2025-03-15", "2025-05-15"]);
+        assert_eq!(due(&rule(Frequency::Yearly, 1, "2024-06-01"), "2026-06-01"), ["2024-06-01", "2025-06-01", "2026-06-01"]);
+    }
+
+    #[test]
+    fn nothing_is_due_before_the_start_date() {
+        assert!(due(&rule(Frequency::Monthly, 1, "2025-03-01"), "2025-02-28").is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_end_date() {
+        let mut monthly = rule(Frequency::Monthly, 1, "2025-01-10");
+        monthly.end = Some(date("2025-03-10"));
+        assert_eq!(due(&monthly, "2025-12-31"), ["2025-01-10", "2025-02-10", "2025-03-10"]);
+        // `until` still applies when it comes before the end date
+        assert_eq!(due(&monthly, "2025-02-09"), ["2025-01-10"]);
+    }
+
+    #[test]
+    fn clamps_the_31st_to_the_end_of_shorter_months() {
+        assert_eq!(due(&rule(Frequency::Monthly, 1, "2025-01-31"), "2025-04-30"), ["2025-01-31", "2025-02-28", "2025-03-31", "2025-04-30"]);
+    }
+
+    #[test]
+    fn skips_months_without_the_31st() {
+        let mut monthly = rule(Frequency::Monthly, 1, "2025-01-31");
+        monthly.short_month = ShortMonth::Skip;
+        assert_eq!(due(&monthly, "2025-05-31"), ["2025-01-31", "2025-03-31", "2025-05-31"]);
+    }
+
+    #[test]
+    fn february_29th_outside_leap_years() {
+        let mut yearly = rule(Frequency::Yearly, 1, "2024-02-29");
+        assert_eq!(due(&yearly, "2028-02-29"), ["2024-02-29", "2025-02-28", "2026-02-28", "2027-02-28", "2028-02-29"]);
+        yearly.short_month = ShortMonth::Skip;
+        assert_eq!(due(&yearly, "2028-02-29"), ["2024-02-29", "2028-02-29"]);
+    }
+
+    #[test]
+    fn occurrences_already_applied_are_not_due_again() {
+        let mut weekly = rule(Frequency::Weekly, 1, "2025-01-01");
+        weekly.applied_until = Some(date("2025-01-15"));
+        assert_eq!(due(&weekly, "2025-01-29"), ["2025-01-22", "2025-01-29"]);
+        weekly.applied_until = Some(date("2025-01-29"));
+        assert!(due(&weekly, "2025-01-29").is_empty());
+    }
+
+    #[test]
+    fn references_identify_the_rule_and_the_occurrence() {
+        let monthly = rule(Frequency::Monthly, 1, "2025-01-31");
+        let reference = monthly.reference(date("2025-02-28"));
+        assert_eq!(reference, "rec:7:2025-02-28");
+        assert_eq!(monthly.to_expense(date("2025-02-28")).reference.as_deref(), Some(reference.as_str()));
+        assert_eq!(rule_id(&reference), Some(7));
+        assert_eq!(rule_id("ofx:checking:42"), None);
+        assert_eq!(rule_id("rec:x:2025-02-28"), None);
+    }
+}
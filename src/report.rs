@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use chrono::Datelike;
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::{money::Money, output::Row, Expense, Kind};
+
+/// Stable output schema of List for the machine-readable formats
+#[derive(Serialize)]
+pub struct ExpenseRow<'a> {
+    pub id: u32,
+    pub date: String,
+    pub amount: Money,
+    pub currency: &'a str,
+    pub category: Option<&'a str>,
+    pub tags: Vec<&'a str>,
+    pub description: &'a str,
+    pub reference: Option<&'a str>,
+    pub kind: &'static str,
+    pub account: Option<&'a str>,
+    pub to_account: Option<&'a str>,
+    pub paid_by: Option<&'a str>,
+    /// In the format of the --split option, e.g. "shares:alice=2,bob=1"
+    pub split: Option<String>,
+}
+
+impl<'a> From<&'a Expense> for ExpenseRow<'a> {
+    fn from(expense: &'a Expense) -> Self {
+        ExpenseRow {
+            id: expense.id,
+            date: expense.date.format("%Y-%m-%d").to_string(),
+            amount: expense.amount,
+            currency: &expense.currency,
+            category: expense.category.as_deref(),
+            tags: expense.tags.iter().map(String::as_str).collect(),
+            description: &expense.description,
+            reference: expense.reference.as_deref(),
+            kind: expense.kind.name(),
+            account: expense.account.as_deref(),
+            to_account: expense.to_account.as_deref(),
+            paid_by: expense.paid_by.as_deref(),
+            split: expense.split.as_ref().map(ToString::to_string),
+        }
+    }
+}
+
+impl Row for ExpenseRow<'_> {
+    const COLUMNS: &'static [&'static str] = &["id", "date", "amount", "currency", "category", "tags", "description", "reference", "kind", "account", "to_account", "paid_by", "split"];
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.date.clone(),
+            self.amount.to_string(),
+            self.currency.to_string(),
+            self.category.unwrap_or_default().to_string(),
+            self.tags.join(","),
+            self.description.to_string(),
+            self.reference.unwrap_or_default().to_string(),
+            self.kind.to_string(),
+            self.account.unwrap_or_default().to_string(),
+            self.to_account.unwrap_or_default().to_string(),
+            self.paid_by.unwrap_or_default().to_string(),
+            self.split.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    /// The grand total
+    Total,
+    /// Total of the expenses in one currency
+    Currency,
+    /// Total of the expenses in one category
+    Category,
+    /// Total of the income
+    Income,
+    /// Income minus expenses
+    Net,
+}
+
+impl Section {
+    fn name(self) -> &'static str {
+        match self {
+            Section::Total => "total",
+            Section::Currency => "currency",
+            Section::Category => "category",
+            Section::Income => "income",
+            Section::Net => "net",
+        }
+    }
+}
+
+/// One line of the summary. `amount` is in `currency`, `base_amount` is the same total converted to the base currency.
+#[derive(Serialize)]
+pub struct SummaryRow {
+    pub section: Section,
+    pub key: Option<String>,
+    pub count: usize,
+    pub amount: Money,
+    pub currency: String,
+    pub base_amount: Money,
+}
+
+impl Row for SummaryRow {
+    const COLUMNS: &'static [&'static str] = &["section", "key", "count", "amount", "currency", "base_amount"];
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.section.name().to_string(),
+            self.key.clone().unwrap_or_default(),
+            self.count.to_string(),
+            self.amount.to_string(),
+            self.currency.clone(),
+            self.base_amount.to_string(),
+        ]
+    }
+}
+
+/// Totals of the records, where `converted[i]` is `records[i].amount` in the base currency. The total, currency and
+/// category rows only count expenses; income and net rows are added when there is any income.
+pub fn summarize(records: &[Expense], converted: &[Money], base_currency: &str, by_category: bool) -> Result<Vec<SummaryRow>, String> {
+    // Transfers only move money between accounts, so they are neither
+    let of_kind = |kind: Kind| records.iter().zip(converted).filter(|(record, _)| record.kind == kind).collect::<Vec<_>>();
+    let (expenses, income) = (of_kind(Kind::Expense), of_kind(Kind::Income));
+    let total = Money::total(expenses.iter().map(|(_, converted)| **converted))?;
+    let mut rows = vec![SummaryRow {
+        section: Section::Total,
+        key: None,
+        count: expenses.len(),
+        amount: total,
+        currency: base_currency.to_string(),
+        base_amount: total,
+    }];
+    // BTreeMaps keep the currencies and categories sorted alphabetically
+    let mut per_currency: BTreeMap<&str, (usize, Money, Money)> = BTreeMap::new();
+    for (expense, converted) in &expenses {
+        let entry = per_currency.entry(&expense.currency).or_default();
+        entry.0 += 1;
+        entry.1 = entry.1.checked_add(expense.amount)?;
+        entry.2 = entry.2.checked_add(**converted)?;
+    }
+    for (currency, (count, amount, base_amount)) in per_currency {
+        rows.push(SummaryRow { section: Section::Currency, key: Some(currency.to_string()), count, amount, currency: currency.to_string(), base_amount });
+    }
+    if by_category {
+        let mut per_category: BTreeMap<Option<&str>, (usize, Money)> = BTreeMap::new();
+        for (expense, converted) in &expenses {
+            let entry = per_category.entry(expense.category.as_deref()).or_default();
+            entry.0 += 1;
+            entry.1 = entry.1.checked_add(**converted)?;
+        }
+        for (category, (count, base_amount)) in per_category {
+            rows.push(SummaryRow {
+                section: Section::Category,
+                key: category.map(String::from),
+                count,
+                amount: base_amount,
+                currency: base_currency.to_string(),
+                base_amount,
+            });
+        }
+    }
+    if !income.is_empty() {
+        let total_income = Money::total(income.iter().map(|(_, converted)| **converted))?;
+        let net = total_income.checked_sub(total)?;
+        for (section, count, amount) in [(Section::Income, income.len(), total_income), (Section::Net, expenses.len() + income.len(), net)] {
+            rows.push(SummaryRow { section, key: None, count, amount, currency: base_currency.to_string(), base_amount: amount });
+        }
+    }
+    Ok(rows)
+}
+
+/// The human readable summary
+pub fn print_summary(rows: &[SummaryRow], base_currency: &str, period_label: &str) {
+    for row in rows {
+        match row.section {
+            Section::Total => println!("Total expenses{period_label}: {} {base_currency}", row.amount),
+            Section::Currency => {}
+            Section::Category => {
+                let category = row.key.as_deref().unwrap_or(crate::UNCATEGORIZED);
+                println!("  {category:<12} {} {base_currency}", row.amount);
+            }
+            Section::Income => println!("Total income{period_label}: {} {base_currency}", row.amount),
+            Section::Net => {
+                println!("Net balance{period_label}: {} {base_currency}", row.amount);
+                let income = rows.iter().find(|r| r.section == Section::Income).map_or(0, |r| r.amount.cents());
+                if income > 0 {
+                    // Share of the income that was not spent
+                    println!("Savings rate: {:.1}%", row.amount.cents() as f64 * 100.0 / income as f64);
+                }
+            }
+        }
+        // Totals in their original currencies, only worth showing when something is not in the base currency
+        if row.section == Section::Total && rows.iter().any(|r| r.section == Section::Currency && r.currency != base_currency) {
+            for row in rows.iter().filter(|r| r.section == Section::Currency) {
+                if row.currency == base_currency {
+                    println!("  {:<12} {}", row.currency, row.amount);
+                } else {
+                    println!("  {:<12} {} (= {} {base_currency})", row.currency, row.amount, row.base_amount);
+                }
+            }
+        }
+    }
+}
+
+/// Grouping of `summary --group-by`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Calendar month, e.g. 2025-01
+    Month,
+    /// ISO week (starting on Monday), e.g. 2025-W03
+    Week,
+    Year,
+    /// Day of the week, from Monday to Sunday
+    Weekday,
+    Category,
+    /// Expenses with several tags count in each of their groups, untagged expenses are grouped as `-`
+    Tag,
+}
+
+impl GroupBy {
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupBy::Month => "month",
+            GroupBy::Week => "week",
+            GroupBy::Year => "year",
+            GroupBy::Weekday => "weekday",
+            GroupBy::Category => "category",
+            GroupBy::Tag => "tag",
+        }
+    }
+
+    /// Groups of an expense as (sort key, label): the labels of periods already sort chronologically,
+    /// weekdays need their number to sort from Monday to Sunday
+    fn keys(self, expense: &Expense) -> Vec<(u32, String)> {
+        match self {
+            GroupBy::Month => vec![(0, expense.date.format("%Y-%m").to_string())],
+            GroupBy::Week => {
+                let week = expense.date.iso_week();
+                vec![(0, format!("{}-W{:02}", week.year(), week.week()))]
+            }
+            GroupBy::Year => vec![(0, expense.date.year().to_string())],
+            GroupBy::Weekday => {
+                let weekday = expense.date.weekday();
+                vec![(weekday.num_days_from_monday(), weekday.to_string())]
+            }
+            GroupBy::Category => vec![(0, expense.category_name().to_string())],
+            GroupBy::Tag if expense.tags.is_empty() => vec![(0, crate::UNCATEGORIZED.to_string())],
+            GroupBy::Tag => expense.tags.iter().map(|tag| (0, tag.clone())).collect(),
+        }
+    }
+}
+
+/// Totals of one group of `summary --group-by`, in the base currency
+#[derive(Serialize)]
+pub struct GroupRow {
+    pub group: String,
+    pub count: usize,
+    pub total: Money,
+    pub average: Money,
+    pub currency: String,
+}
+
+impl Row for GroupRow {
+    const COLUMNS: &'static [&'static str] = &["group", "count", "total", "average", "currency"];
+    fn values(&self) -> Vec<String> {
+        vec![self.group.clone(), self.count.to_string(), self.total.to_string(), self.average.to_string(), self.currency.clone()]
+    }
+}
+
+/// Totals per group, sorted by period (or alphabetically for categories and tags)
+pub fn group(expenses: &[Expense], converted: &[Money], base_currency: &str, group_by: GroupBy) -> Result<Vec<GroupRow>, String> {
+    let mut groups: BTreeMap<(u32, String), (usize, Money)> = BTreeMap::new();
+    for (expense, converted) in expenses.iter().zip(converted) {
+        for key in group_by.keys(expense) {
+            let entry = groups.entry(key).or_default();
+            entry.0 += 1;
+            entry.1 = entry.1.checked_add(*converted)?;
+        }
+    }
+    Ok(groups
+        .into_iter()
+        .map(|((_, group), (count, total))| GroupRow { group, count, total, average: total.divide(count), currency: base_currency.to_string() })
+        .collect())
+}
+
+/// The human readable table of groups, followed by the grand total
+pub fn print_groups(rows: &[GroupRow], group_by: GroupBy, total: Money, base_currency: &str, period_label: &str) {
+    if rows.is_empty() {
+        println!("Nothing to summarize.");
+        return;
+    }
+    let mut title = group_by.name().to_string();
+    title[..1].make_ascii_uppercase();
+    println!("{title:<12} | Count | {:<14} | Average", "Total");
+    for row in rows {
+        let total = format!("{} {}", row.total, row.currency);
+        println!("{:<12} | {:>5} | {total:<14} | {} {}", row.group, row.count, row.average, row.currency);
+    }
+    println!("Total expenses{period_label}: {total} {base_currency}");
+}
@@ -0,0 +1,319 @@
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::{money::Money, Expense, Kind};
+
+/// How an expense is divided between the people who share it, written as `<mode>:<participants>`:
+/// `equal:alice,bob`, `shares:alice=2,bob=1` or `exact:alice=30.00,bob=20.00` (`equal:` can be omitted)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Split {
+    Equal(Vec<String>),
+    /// Each participant pays in proportion to their number of shares
+    Shares(Vec<(String, u32)>),
+    /// Each participant pays a fixed amount; the amounts must add up to the expense's amount
+    Exact(Vec<(String, Money)>),
+}
+
+/// People's names are case-insensitive, like categories
+pub fn parse_name(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.contains([',', '=', ':']) {
+        return Err(format!("Invalid name '{name}' (names must be non-empty and cannot contain , = or :)"));
+    }
+    Ok(name)
+}
+
+impl FromStr for Split {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, participants) = s.split_once(':').unwrap_or(("equal", s));
+        let entries: Vec<&str> = participants.split(',').map(str::trim).filter(|entry| !entry.is_empty()).collect();
+        if entries.is_empty() {
+            return Err(format!("Invalid split '{s}': no participants"));
+        }
+        let split = match mode.trim().to_lowercase().as_str() {
+            "equal" => Split::Equal(entries.into_iter().map(parse_name).collect::<Result<_, _>>()?),
+            "shares" => Split::Shares(
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let (name, shares) = split_pair(s, entry)?;
+                        let shares = shares.trim().parse().map_err(|_| format!("Invalid split '{s}': '{shares}' is not a number of shares"))?;
+                        Ok((parse_name(name)?, shares))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            "exact" => Split::Exact(
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let (name, amount) = split_pair(s, entry)?;
+                        Ok((parse_name(name)?, amount.parse()?))
+                    })
+                    .collect::<Result<_, String>>()?,
+            ),
+            _ => return Err(format!("Invalid split mode '{mode}' (expected equal, shares or exact)")),
+        };
+        let names = split.names();
+        if (1..names.len()).any(|i| names[..i].contains(&names[i])) {
+            return Err(format!("Invalid split '{s}': a participant is listed twice"));
+        }
+        if let Split::Shares(shares) = &split {
+            if shares.iter().all(|(_, shares)| *shares == 0) {
+                return Err(format!("Invalid split '{s}': there must be at least one share"));
+            }
+        }
+        Ok(split)
+    }
+}
+
+fn split_pair<'a>(split: &str, entry: &'a str) -> Result<(&'a str, &'a str), String> {
+    entry.split_once('=').ok_or_else(|| format!("Invalid split '{split}': expected <name>=<value> but found '{entry}'"))
+}
+
+impl Display for Split {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<String> = match self {
+            Split::Equal(names) => names.clone(),
+            Split::Shares(shares) => shares.iter().map(|(name, shares)| format!("{name}={shares}")).collect(),
+            Split::Exact(amounts) => amounts.iter().map(|(name, amount)| format!("{name}={amount}")).collect(),
+        };
+        let mode = match self {
+            Split::Equal(_) => "equal",
+            Split::Shares(_) => "shares",
+            Split::Exact(_) => "exact",
+        };
+        write!(f, "{mode}:{}", entries.join(","))
+    }
+}
+
+/// Splits are stored in a single column in the format they are typed in
+impl Serialize for Split {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Split {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Split {
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Split::Equal(names) => names.iter().map(String::as_str).collect(),
+            Split::Shares(shares) => shares.iter().map(|(name, _)| name.as_str()).collect(),
+            Split::Exact(amounts) => amounts.iter().map(|(name, _)| name.as_str()).collect(),
+        }
+    }
+
+    /// Checks that a record can be split: someone paid it, it is not a transfer, and exact amounts add up to its amount
+    pub fn check(record: &Expense) -> Result<(), String> {
+        let Some(split) = &record.split else {
+            return Ok(());
+        };
+        if record.paid_by.is_none() {
+            return Err("A split expense needs --paid-by".into());
+        }
+        if record.kind == Kind::Transfer {
+            return Err("Transfers can't be split".into());
+        }
+        if let Split::Exact(amounts) = split {
+            let total = Money::total(amounts.iter().map(|(_, amount)| *amount))?;
+            if total != record.amount {
+                return Err(format!("The exact amounts of the split add up to {total} instead of {}", record.amount));
+            }
+        }
+        Ok(())
+    }
+
+    /// The part of `amount` each participant owes. Cents that can't be divided evenly go to the first participants,
+    /// so the parts always add up to `amount`.
+    pub fn parts(&self, amount: Money) -> Vec<(&str, Money)> {
+        let weights: Vec<(&str, i64)> = match self {
+            Split::Equal(names) => names.iter().map(|name| (name.as_str(), 1)).collect(),
+            Split::Shares(shares) => shares.iter().map(|(name, shares)| (name.as_str(), *shares as i64)).collect(),
+            Split::Exact(amounts) => {
+                // Exact amounts are in the expense's currency, so they are rescaled when the amount was converted
+                amounts.iter().map(|(name, amount)| (name.as_str(), amount.cents())).collect()
+            }
+        };
+        let total_weight: i64 = weights.iter().map(|(_, weight)| weight).sum();
+        if total_weight == 0 {
+            return Vec::new();
+        }
+        let cents = amount.cents() as i128;
+        let mut parts: Vec<(&str, i64)> = weights
+            .iter()
+            .map(|(name, weight)| (*name, (cents * *weight as i128 / total_weight as i128) as i64))
+            .collect();
+        let remainder = amount.cents() - parts.iter().map(|(_, part)| part).sum::<i64>();
+        // Less than one cent was lost per participant, and participants without a weight don't get any
+        let weighted: Vec<usize> = (0..parts.len()).filter(|&i| weights[i].1 != 0).collect();
+        for i in 0..remainder.unsigned_abs() as usize {
+            parts[weighted[i % weighted.len()]].1 += remainder.signum();
+        }
+        parts.into_iter().map(|(name, part)| (name, Money::from_cents(part))).collect()
+    }
+}
+
+/// What one person paid for the others minus what they owe, positive when they are owed money
+pub fn balances(records: &[Expense], converted: &[Money]) -> Result<BTreeMap<String, Money>, String> {
+    let mut balances: BTreeMap<String, Money> = BTreeMap::new();
+    for (record, converted) in records.iter().zip(converted) {
+        let (Some(paid_by), Some(split)) = (&record.paid_by, &record.split) else {
+            continue;
+        };
+        // Income split between people is shared the other way around
+        let amount = if record.kind == Kind::Income { -*converted } else { *converted };
+        let balance = balances.entry(paid_by.clone()).or_default();
+        *balance = balance.checked_add(amount)?;
+        for (name, part) in split.parts(amount) {
+            let balance = balances.entry(name.to_string()).or_default();
+            *balance = balance.checked_sub(part)?;
+        }
+    }
+    Ok(balances)
+}
+
+/// A payment that settles (part of) a debt
+pub struct Payment {
+    pub from: String,
+    pub to: String,
+    pub amount: Money,
+}
+
+/// Above this many people with a balance, looking for the fewest payments (which tries every subset of them)
+/// would take too long, and the greedy settlement of everyone at once is used instead
+const MAX_EXACT_SETTLE: usize = 16;
+
+/// The fewest payments that bring every balance to zero. People are first split into as many groups whose
+/// balances add up to zero as possible: each group of `k` people can be settled with `k - 1` payments, and no
+/// settlement needs fewer than the number of people minus the number of such groups.
+pub fn settle(balances: &BTreeMap<String, Money>) -> Vec<Payment> {
+    let people: Vec<(&str, Money)> = balances
+        .iter()
+        .filter(|(_, balance)| **balance != Money::default())
+        .map(|(name, balance)| (name.as_str(), *balance))
+        .collect();
+    if people.len() > MAX_EXACT_SETTLE {
+        return settle_greedy(&people);
+    }
+    zero_sum_groups(&people).iter().flat_map(|group| settle_greedy(group)).collect()
+}
+
+/// Splits the people into the largest number of groups whose balances add up to zero
+fn zero_sum_groups<'a>(people: &[(&'a str, Money)]) -> Vec<Vec<(&'a str, Money)>> {
+    let count = people.len();
+    let full = (1usize << count) - 1;
+    // sums[mask] is the total balance of the people in `mask`
+    let mut sums = vec![0i128; full + 1];
+    for mask in 1..=full {
+        let lowest = mask.trailing_zeros() as usize;
+        sums[mask] = sums[mask & (mask - 1)] + people[lowest].1.cents() as i128;
+    }
+    // groups[mask] is the most zero-sum groups the people in `mask` can be split into, when adding them one by one
+    // and closing a group every time the people added so far add up to zero
+    let mut groups = vec![0usize; full + 1];
+    for mask in 1..=full {
+        let best = (0..count).filter(|i| mask & (1 << i) != 0).map(|i| groups[mask & !(1 << i)]).max().unwrap_or(0);
+        groups[mask] = best + usize::from(sums[mask] == 0);
+    }
+    // Removes people in an order that reaches the best count, the zero-sum masks along the way delimit the groups
+    let mut result = Vec::new();
+    let (mut mask, mut group_end) = (full, full);
+    while mask != 0 {
+        let closes = usize::from(sums[mask] == 0);
+        let Some(i) = (0..count).find(|&i| mask & (1 << i) != 0 && groups[mask & !(1 << i)] + closes == groups[mask]) else {
+            break;
+        };
+        mask &= !(1 << i);
+        if sums[mask] == 0 {
+            let group = group_end & !mask;
+            result.push((0..count).filter(|i| group & (1 << i) != 0).map(|i| people[i]).collect());
+            group_end = mask;
+        }
+    }
+    result.reverse();
+    result
+}
+
+/// Payments that bring every balance of the group to zero. The largest debt is always paid to the largest creditor,
+/// which needs at most one payment less than the number of people with a balance.
+fn settle_greedy(people: &[(&str, Money)]) -> Vec<Payment> {
+    let mut debtors: Vec<(String, Money)> = Vec::new();
+    let mut creditors: Vec<(String, Money)> = Vec::new();
+    for (name, balance) in people {
+        if *balance < Money::default() {
+            debtors.push((name.to_string(), -*balance));
+        } else if *balance > Money::default() {
+            creditors.push((name.to_string(), *balance));
+        }
+    }
+    let mut payments = Vec::new();
+    loop {
+        // Largest amounts first, ties broken by name so the result is stable
+        let largest = |people: &Vec<(String, Money)>| (0..people.len()).max_by(|&a, &b| people[a].1.cmp(&people[b].1).then(people[b].0.cmp(&people[a].0)));
+        let (Some(debtor), Some(creditor)) = (largest(&debtors), largest(&creditors)) else {
+            break;
+        };
+        let amount = debtors[debtor].1.min(creditors[creditor].1);
+        payments.push(Payment { from: debtors[debtor].0.clone(), to: creditors[creditor].0.clone(), amount });
+        debtors[debtor].1 -= amount;
+        creditors[creditor].1 -= amount;
+        debtors.retain(|(_, owed)| *owed > Money::default());
+        creditors.retain(|(_, owed)| *owed > Money::default());
+    }
+    payments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(split: &str, cents: i64) -> Vec<(String, i64)> {
+        let split: Split = split.parse().unwrap();
+        split.parts(Money::from_cents(cents)).into_iter().map(|(name, part)| (name.to_string(), part.cents())).collect()
+    }
+
+    fn owed(parts: &[(&str, i64)]) -> Vec<(String, i64)> {
+        parts.iter().map(|(name, cents)| (name.to_string(), *cents)).collect()
+    }
+
+    #[test]
+    fn parses_splits() {
+        assert_eq!("Alice, bob".parse(), Ok(Split::Equal(vec!["alice".into(), "bob".into()])));
+        assert_eq!("shares:alice=2,bob=1".parse(), Ok(Split::Shares(vec![("alice".into(), 2), ("bob".into(), 1)])));
+        assert!("equal:alice,alice".parse::<Split>().is_err());
+        assert!("shares:alice=0".parse::<Split>().is_err());
+        assert!("thirds:alice".parse::<Split>().is_err());
+        let split: Split = "exact:alice=30,bob=20".parse().unwrap();
+        assert_eq!(split.to_string().parse(), Ok(split));
+    }
+
+    #[test]
+    fn gives_remaining_cents_to_the_first_participants() {
+        assert_eq!(parts("a,b,c", 1000), owed(&[("a", 334), ("b", 333), ("c", 333)]));
+        assert_eq!(parts("a,b,c", 1001), owed(&[("a", 334), ("b", 334), ("c", 333)]));
+        assert_eq!(parts("a,b,c", -1000), owed(&[("a", -334), ("b", -333), ("c", -333)]));
+        assert_eq!(parts("shares:a=0,b=1,c=1", 101), owed(&[("a", 0), ("b", 51), ("c", 50)]));
+        assert_eq!(parts("shares:a=2,b=1", 100), owed(&[("a", 67), ("b", 33)]));
+    }
+
+    #[test]
+    fn rescales_exact_amounts_of_converted_expenses() {
+        assert_eq!(parts("exact:a=30,b=20", 5000), owed(&[("a", 3000), ("b", 2000)]));
+        assert_eq!(parts("exact:a=30,b=20", 1000), owed(&[("a", 600), ("b", 400)]));
+    }
+
+    #[test]
+    fn settles_with_the_fewest_payments() {
+        let balances: BTreeMap<String, Money> =
+            [("a", -600), ("b", -400), ("c", 400), ("d", 300), ("e", 300)].into_iter().map(|(name, cents)| (name.to_string(), Money::from_cents(cents))).collect();
+        let payments: Vec<(String, String, i64)> = settle(&balances).into_iter().map(|payment| (payment.from, payment.to, payment.amount.cents())).collect();
+        let payment = |from: &str, to: &str, cents: i64| (from.to_string(), to.to_string(), cents);
+        assert_eq!(payments, [payment("b", "c", 400), payment("a", "d", 300), payment("a", "e", 300)]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use clap::ValueEnum;
+use crate::{money::Money, Expense};
+
+/// Groups whose typical expense outliers are compared to
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlierGroup {
+    Category,
+    Month,
+}
+
+/// Groups with fewer expenses have no meaningful quartiles, so they never have outliers
+const MIN_GROUP_SIZE: usize = 4;
+
+/// Descriptive statistics of a set of amounts, all in the same currency
+pub struct Stats {
+    pub count: usize,
+    pub mean: Money,
+    pub median: Money,
+    pub min: Money,
+    pub max: Money,
+    /// Sample standard deviation (zero for a single expense)
+    pub std_dev: Money,
+    pub p90: Money,
+    pub p99: Money,
+}
+
+impl Stats {
+    /// None when there are no amounts
+    pub fn compute(amounts: &[Money]) -> Option<Stats> {
+        let mut cents: Vec<i64> = amounts.iter().map(|amount| amount.cents()).collect();
+        cents.sort_unstable();
+        let (&min, &max) = (cents.first()?, cents.last()?);
+        let count = cents.len();
+        let mean = cents.iter().map(|&c| c as f64).sum::<f64>() / count as f64;
+        let variance = if count > 1 {
+            cents.iter().map(|&c| (c as f64 - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+        Some(Stats {
+            count,
+            mean: Money::from_cents(mean.round() as i64),
+            median: percentile(&cents, 0.5),
+            min: Money::from_cents(min),
+            max: Money::from_cents(max),
+            std_dev: Money::from_cents(variance.sqrt().round() as i64),
+            p90: percentile(&cents, 0.9),
+            p99: percentile(&cents, 0.99),
+        })
+    }
+}
+
+/// Percentile of sorted amounts, interpolating linearly between the two closest ranks
+fn percentile(sorted_cents: &[i64], p: f64) -> Money {
+    let rank = p * (sorted_cents.len() - 1) as f64;
+    let (low, high) = (sorted_cents[rank.floor() as usize], sorted_cents[rank.ceil() as usize]);
+    let value = low as f64 + (high - low) as f64 * rank.fract();
+    Money::from_cents(value.round() as i64)
+}
+
+/// An expense far above the typical expense of its group
+pub struct Outlier<'a> {
+    pub expense: &'a Expense,
+    pub group: String,
+    /// Median of the group
+    pub typical: Money,
+}
+
+/// Expenses above the upper Tukey fence of their group: Q3 + `factor` × (Q3 - Q1), compared in the base currency
+pub fn outliers<'a>(expenses: &'a [Expense], converted: &[Money], group_by: OutlierGroup, factor: f64) -> Vec<Outlier<'a>> {
+    let mut groups: BTreeMap<String, Vec<(&Expense, Money)>> = BTreeMap::new();
+    for (expense, converted) in expenses.iter().zip(converted) {
+        let group = match group_by {
+            OutlierGroup::Category => expense.category_name().to_string(),
+            OutlierGroup::Month => expense.date.format("%Y-%m").to_string(),
+        };
+        groups.entry(group).or_default().push((expense, *converted));
+    }
+    let mut outliers = Vec::new();
+    for (group, members) in groups {
+        if members.len() < MIN_GROUP_SIZE {
+            continue;
+        }
+        let mut cents: Vec<i64> = members.iter().map(|(_, amount)| amount.cents()).collect();
+        cents.sort_unstable();
+        let (q1, q3) = (percentile(&cents, 0.25).cents(), percentile(&cents, 0.75).cents());
+        let fence = q3 as f64 + factor * (q3 - q1) as f64;
+        let typical = percentile(&cents, 0.5);
+        for (expense, amount) in members {
+            if amount.cents() as f64 > fence {
+                outliers.push(Outlier { expense, group: group.clone(), typical });
+            }
+        }
+    }
+    outliers.sort_by_key(|outlier| (outlier.expense.date, outlier.expense.id));
+    outliers
+}
+
+pub fn print(stats: &Stats, outliers: &[Outlier], group_by: OutlierGroup, base_currency: &str, period_label: &str) {
+    let plural = if stats.count == 1 { "" } else { "s" };
+    println!("Statistics of {} expense{plural}{period_label} (in {base_currency}):", stats.count);
+    let lines = [
+        ("Mean", stats.mean),
+        ("Median", stats.median),
+        ("Min", stats.min),
+        ("Max", stats.max),
+        ("Std. dev.", stats.std_dev),
+        ("p90", stats.p90),
+        ("p99", stats.p99),
+    ];
+    for (name, value) in lines {
+        println!("  {name:<12} {value}");
+    }
+    if outliers.is_empty() {
+        return;
+    }
+    let group_name = match group_by {
+        OutlierGroup::Category => "category",
+        OutlierGroup::Month => "month",
+    };
+    println!("Outliers (far above the typical expense of their {group_name}):");
+    println!("ID  | Date       | Amount         | Category     | Description");
+    for outlier in outliers {
+        println!("{} (typical for {}: {} {base_currency})", outlier.expense, outlier.group, outlier.typical);
+    }
+}
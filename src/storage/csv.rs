@@ -0,0 +1,302 @@
+use std::{error::Error, fmt::Display, fs::{File, OpenOptions}, io::{Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}};
+use chrono::NaiveDate;
+use crate::{atomic, sidecar_path, Expense};
+use super::Storage;
+
+/// Columns written by serializing `Expense`, in the order of its fields (keep in sync with the struct)
+const HEADERS: &str = "id;amount;description;date;category;tags;currency;reference;kind;account;to_account;paid_by;split";
+
+/// Ledger stored in a `;` separated CSV file. New expenses are appended, every other change rewrites the whole file.
+pub struct CsvStorage {
+    file_path: PathBuf,
+    default_currency: String,
+}
+
+impl CsvStorage {
+    /// Creates the file (with headers) if it does not exist yet
+    pub fn open(file_path: &Path, default_currency: &str) -> Result<Self, std::io::Error> {
+        create_db(file_path)?;
+        Ok(CsvStorage { file_path: file_path.to_path_buf(), default_currency: default_currency.to_string() })
+    }
+
+    /// Reading for a write is strict: rewriting the file would drop the malformed rows for good, so it is refused.
+    /// Otherwise they are skipped with a warning.
+    fn read(&self, for_write: bool) -> Result<Vec<Expense>, Box<dyn Error>> {
+        let (mut expenses, bad_rows) = read_db(&self.file_path)?;
+        if !bad_rows.is_empty() {
+            let report: Vec<String> = bad_rows.iter().map(|row| format!("  {row}")).collect();
+            let report = report.join("\n");
+            if for_write {
+                return Err(format!(
+                    "{} has {} malformed row(s) and will not be overwritten (run `doctor` to repair them):\n{report}",
+                    self.file_path.display(), bad_rows.len()
+                ).into());
+            }
+            eprintln!("Warning: skipping {} malformed row(s) of {} (run `doctor` to repair them):\n{report}", bad_rows.len(), self.file_path.display());
+        }
+        // Files written by older versions have no currency column
+        for expense in expenses.iter_mut().filter(|expense| expense.currency.is_empty()) {
+            expense.currency = self.default_currency.clone();
+        }
+        Ok(expenses)
+    }
+}
+
+impl Storage for CsvStorage {
+    fn load(&mut self) -> Result<Vec<Expense>, Box<dyn Error>> {
+        self.read(false)
+    }
+
+    /// Only the id column is parsed, no row is deserialized. Rows quarantined by `doctor` keep their ids reserved,
+    /// so they can be pasted back into the ledger later.
+    fn next_id(&mut self) -> Result<u32, Box<dyn Error>> {
+        let quarantine_path = sidecar_path(&self.file_path, "quarantine");
+        let quarantined = if quarantine_path.exists() { max_row_id(&quarantine_path)? } else { None };
+        let max_id = max_row_id(&self.file_path)?.max(quarantined);
+        Ok(max_id.map_or(1, |max_id| max_id.max(1) + 1))
+    }
+
+    /// Only the date column of the other rows is looked at, they are not deserialized.
+    /// Malformed rows are skipped without a warning, `load` and `doctor` report them.
+    fn on_date(&mut self, date: NaiveDate) -> Result<Vec<Expense>, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(b';')
+            .flexible(true)
+            .from_path(&self.file_path)?;
+        let headers = reader.byte_headers()?.clone();
+        let column = headers.iter().position(|header| header == b"date").ok_or_else(|| format!("{} has no date column", self.file_path.display()))?;
+        let date = date.to_string();
+        let mut expenses = Vec::new();
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            if record.get(column).map(<[u8]>::trim_ascii) != Some(date.as_bytes()) {
+                continue;
+            }
+            if let Ok(mut expense) = parse_row(&headers, record.clone()) {
+                // Files written by older versions have no currency column
+                if expense.currency.is_empty() {
+                    expense.currency = self.default_currency.clone();
+                }
+                expenses.push(expense);
+            }
+        }
+        Ok(expenses)
+    }
+
+    fn insert(&mut self, mut expense: Expense) -> Result<u32, Box<dyn Error>> {
+        expense.id = self.next_id()?;
+        if headers_match(&self.file_path)? {
+            return append_db(&self.file_path, expense);
+        }
+        // Files written by older versions have other columns, rewriting them migrates them to the current ones
+        let mut expenses = self.read(true)?;
+        let id = expense.id;
+        expenses.push(expense);
+        write_db(&self.file_path, expenses)?;
+        Ok(id)
+    }
+
+    fn update(&mut self, expense: Expense) -> Result<bool, Box<dyn Error>> {
+        let mut expenses = self.read(true)?;
+        let Some(entry) = expenses.iter_mut().find(|entry| entry.id == expense.id) else {
+            return Ok(false);
+        };
+        *entry = expense;
+        write_db(&self.file_path, expenses)?;
+        Ok(true)
+    }
+
+    fn delete(&mut self, id: u32) -> Result<bool, Box<dyn Error>> {
+        let mut expenses = self.read(true)?;
+        let previous_len = expenses.len();
+        expenses.retain(|x| x.id != id);
+        // Unequal lengths means the operation was successful 
+        if previous_len == expenses.len() {
+            return Ok(false);
+        }
+        write_db(&self.file_path, expenses)?;
+        Ok(true)
+    }
+
+    fn replace_all(&mut self, expenses: Vec<Expense>) -> Result<(), Box<dyn Error>> {
+        self.read(true)?;
+        write_db(&self.file_path, expenses)?;
+        Ok(())
+    }
+}
+
+fn create_db(file_path: &Path) -> Result<(), std::io::Error> {
+    if !file_path.exists() {
+        // create_new so that a concurrent invocation that created the file first is never truncated
+        let mut file = match File::options().write(true).create_new(true).open(file_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Create a new CSV file with headers
+        let _ = file.write_all(format!("{HEADERS}\n").as_bytes());
+    }
+    Ok(())
+}
+
+/// Whether the file has the columns `Expense` is serialized with, which is required to append rows to it
+fn headers_match(file_path: &Path) -> Result<bool, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b';')
+        .from_path(file_path)?;
+    let expected = HEADERS.split(';');
+    Ok(reader.byte_headers()?.iter().eq(expected.map(str::as_bytes)))
+}
+
+/// Highest id in the first column of a `;` separated file with headers
+fn max_row_id(file_path: &Path) -> Result<Option<u32>, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b';')
+        .flexible(true)
+        .from_path(file_path)?;
+    let mut max_id = None;
+    let mut record = csv::ByteRecord::new();
+    while reader.read_byte_record(&mut record)? {
+        // Malformed rows still reserve their id, if it can be read
+        let id = record.get(0).and_then(|id| std::str::from_utf8(id).ok()).and_then(|id| id.trim().parse::<u32>().ok());
+        max_id = max_id.max(id);
+    }
+    Ok(max_id)
+}
+
+/// Appends a single row (the expense already has its id) instead of rewriting the file, returning the id.
+/// The previous version is still kept as a backup, copied byte for byte without parsing it.
+fn append_db(file_path: &Path, expense: Expense) -> Result<u32, Box<dyn Error>> {
+    atomic::backup(file_path)?;
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .delimiter(b';')
+        .from_writer(vec![]);
+    writer.serialize(&expense)?;
+    let mut row = writer.into_inner().map_err(|e| e.into_error())?;
+
+    let mut file = OpenOptions::new().read(true).append(true).open(file_path)?;
+    // The file may not end with a line break (files created by older versions don't)
+    if file.seek(SeekFrom::End(0))? > 0 {
+        let mut last_byte = [0u8];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last_byte)?;
+        if last_byte[0] != b'\n' {
+            row.insert(0, b'\n');
+        }
+    }
+    // A single write, so a crash can at worst leave one incomplete last row, which `doctor` reports
+    file.write_all(&row)?;
+    file.sync_data()?;
+    Ok(expense.id)
+}
+
+/// A row of the ledger that could not be deserialized, kept untouched so it can be reported and repaired
+pub struct BadRow {
+    pub line: u64,
+    pub record: csv::ByteRecord,
+    pub error: String,
+}
+
+impl BadRow {
+    /// The row as it would be written back to the file
+    fn raw(&self) -> String {
+        let mut writer = csv::WriterBuilder::new().delimiter(b';').flexible(true).from_writer(vec![]);
+        let _ = writer.write_byte_record(&self.record);
+        let bytes = writer.into_inner().unwrap_or_default();
+        String::from_utf8_lossy(&bytes).trim_end().to_string()
+    }
+}
+
+impl Display for BadRow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}\n    {}", self.line, self.raw(), self.error)
+    }
+}
+
+/// Reads CSV file (columns separated by ; to avoid issues with different decimal separator (dot or comma)) using Serde for deserialization.
+/// Rows that can't be deserialized are returned separately instead of being dropped, so they are never lost on the next write.
+fn read_db(file_path: &Path) -> Result<(Vec<Expense>, Vec<BadRow>), csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b';')
+        // Rows with a wrong number of columns are reported as bad rows rather than aborting the whole read
+        .flexible(true)
+        .from_path(file_path)?;
+    let headers = reader.byte_headers()?.clone();
+
+    let mut expenses = Vec::new();
+    let mut bad_rows = Vec::new();
+    for record in reader.byte_records() {
+        match parse_row(&headers, record?) {
+            Ok(expense) => expenses.push(expense),
+            Err(bad_row) => bad_rows.push(bad_row),
+        }
+    }
+    Ok((expenses, bad_rows))
+}
+
+pub fn parse_row(headers: &csv::ByteRecord, record: csv::ByteRecord) -> Result<Expense, BadRow> {
+    let line = record.position().map_or(0, |position| position.line());
+    if record.len() != headers.len() {
+        let error = format!("expected {} columns but found {}", headers.len(), record.len());
+        return Err(BadRow { line, record, error });
+    }
+    record.deserialize::<Expense>(Some(headers)).map_err(|error| {
+        let error = match error.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => error.to_string(),
+        };
+        BadRow { line, record, error }
+    })
+}
+
+/// Writing entries to the CSV file using Serde for serialization
+/// The file is replaced atomically and its previous version is kept as a backup (see `restore`)
+fn write_db(file_path: &Path, records: Vec<Expense>) -> Result<(), csv::Error> {
+    atomic::backup(file_path)?;
+    let mut builder = csv::WriterBuilder::new();
+    builder.has_headers(true).delimiter(b';');
+    atomic::write_csv(file_path, &builder, |writer| {
+        for record in records {
+            writer.serialize(record)?;
+        }
+        Ok(())
+    })
+}
+
+/// Writing rows to the CSV file exactly as they were read, used when repairing a ledger that has malformed rows
+pub fn write_raw_db(file_path: &Path, headers: &csv::ByteRecord, records: &[csv::ByteRecord]) -> Result<(), csv::Error> {
+    atomic::backup(file_path)?;
+    let mut builder = csv::WriterBuilder::new();
+    builder.delimiter(b';').flexible(true);
+    atomic::write_csv(file_path, &builder, |writer| {
+        writer.write_byte_record(headers)?;
+        for record in records {
+            writer.write_byte_record(record)?;
+        }
+        Ok(())
+    })
+}
+
+/// Puts the backup back in place of the ledger. The replaced version becomes the new backup, so restoring twice undoes it.
+pub fn restore_db(file_path: &Path) -> Result<(), Box<dyn Error>> {
+    let backup_path = atomic::backup_path(file_path);
+    if !backup_path.exists() {
+        return Err(format!("There is no backup of {} to restore ({} does not exist)", file_path.display(), backup_path.display()).into());
+    }
+    let (headers, records) = {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .delimiter(b';')
+            .flexible(true)
+            .from_path(&backup_path)?;
+        let headers = reader.byte_headers()?.clone();
+        (headers, reader.byte_records().collect::<Result<Vec<_>, _>>()?)
+    };
+    write_raw_db(file_path, &headers, &records)?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::{error::Error, path::Path};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use crate::{filter_records, Expense, Filter};
+
+pub mod csv;
+pub mod sqlite;
+
+/// Persistence of the ledger. Every backend stores the same `Expense` records, so ledgers can be migrated between them.
+pub trait Storage {
+    /// Every expense of the ledger, ordered by id
+    fn load(&mut self) -> Result<Vec<Expense>, Box<dyn Error>>;
+
+    /// The id the next inserted expense will get
+    fn next_id(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(next_id(&self.load()?))
+    }
+
+    /// Stores a new expense under the next free id, which is returned (the id of `expense` is ignored)
+    fn insert(&mut self, expense: Expense) -> Result<u32, Box<dyn Error>>;
+
+    /// Replaces the expense with the same id, returns false if there is none
+    fn update(&mut self, expense: Expense) -> Result<bool, Box<dyn Error>>;
+
+    /// Removes the expense with the given id, returns false if there is none
+    fn delete(&mut self, id: u32) -> Result<bool, Box<dyn Error>>;
+
+    /// Replaces the whole ledger, keeping the ids of the given expenses
+    fn replace_all(&mut self, expenses: Vec<Expense>) -> Result<(), Box<dyn Error>>;
+
+    fn get(&mut self, id: u32) -> Result<Option<Expense>, Box<dyn Error>> {
+        Ok(self.load()?.into_iter().find(|expense| expense.id == id))
+    }
+
+    /// The expenses on `date`, ordered by id (e.g. to look for duplicates of a new one)
+    fn on_date(&mut self, date: NaiveDate) -> Result<Vec<Expense>, Box<dyn Error>> {
+        Ok(self.load()?.into_iter().filter(|expense| expense.date == date).collect())
+    }
+
+    /// The expenses matching the filter of List/Summary
+    fn query(&mut self, filter: &Filter) -> Result<Vec<Expense>, Box<dyn Error>> {
+        let mut expenses = self.load()?;
+        filter_records(&mut expenses, filter)?;
+        Ok(expenses)
+    }
+}
+
+/// Storage backends that can be selected with --backend
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `;` separated CSV file
+    Csv,
+    /// Embedded SQLite database
+    Sqlite,
+}
+
+impl Backend {
+    /// Files ending with .db, .sqlite or .sqlite3 are SQLite databases, anything else is CSV
+    pub fn detect(file_path: &Path) -> Backend {
+        let extension = file_path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "db" | "sqlite" | "sqlite3" => Backend::Sqlite,
+            _ => Backend::Csv,
+        }
+    }
+}
+
+/// Opens (creating it if needed) the ledger at `file_path`. Expenses without a currency are given `default_currency`.
+pub fn open(file_path: &Path, backend: Backend, default_currency: &str) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+    Ok(match backend {
+        Backend::Csv => Box::new(csv::CsvStorage::open(file_path, default_currency)?),
+        Backend::Sqlite => Box::new(sqlite::SqliteStorage::open(file_path)?),
+    })
+}
+
+/// Ids are never reused while the expense with the highest id exists
+fn next_id(expenses: &[Expense]) -> u32 {
+    if expenses.is_empty() {
+        1
+    } else {
+        expenses.iter().fold(1, |acc, expense| expense.id.max(acc)) + 1 
+    }
+}
@@ -0,0 +1,167 @@
+use std::{error::Error, path::Path};
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use rusqlite::{params, types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef}, Connection, Row, ToSql};
+use crate::{money::Money, split::Split, tag_list, Expense, Kind};
+use super::Storage;
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many of them a database already has.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE expenses (
+        id INTEGER PRIMARY KEY,
+        date TEXT NOT NULL,
+        description TEXT NOT NULL,
+        amount INTEGER NOT NULL, -- in cents
+        category TEXT,
+        tags TEXT NOT NULL DEFAULT '', -- comma separated, like in the CSV file
+        currency TEXT NOT NULL
+    )",
+    "ALTER TABLE expenses ADD COLUMN reference TEXT",
+    "ALTER TABLE expenses ADD COLUMN kind TEXT NOT NULL DEFAULT 'expense'",
+    "ALTER TABLE expenses ADD COLUMN account TEXT;
+     ALTER TABLE expenses ADD COLUMN to_account TEXT",
+    "ALTER TABLE expenses ADD COLUMN paid_by TEXT;
+     ALTER TABLE expenses ADD COLUMN split TEXT",
+];
+
+const COLUMNS: &str = "id, date, description, amount, category, tags, currency, reference, kind, account, to_account, paid_by, split";
+
+/// Ledger stored in an embedded SQLite database (a single file, no server needed)
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(file_path: &Path) -> Result<Self, rusqlite::Error> {
+        let mut connection = Connection::open(file_path)?;
+        migrate(&mut connection)?;
+        Ok(SqliteStorage { connection })
+    }
+}
+
+fn migrate(connection: &mut Connection) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+    let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+    }
+    transaction.commit()
+}
+
+fn from_row(row: &Row) -> Result<Expense, rusqlite::Error> {
+    Ok(Expense {
+        id: row.get("id")?,
+        date: row.get("date")?,
+        description: row.get("description")?,
+        amount: Money::from_cents(row.get("amount")?),
+        category: row.get("category")?,
+        tags: tag_list::split(&row.get::<_, String>("tags")?),
+        currency: row.get("currency")?,
+        reference: row.get("reference")?,
+        kind: row.get("kind")?,
+        account: row.get("account")?,
+        to_account: row.get("to_account")?,
+        paid_by: row.get("paid_by")?,
+        split: row.get("split")?,
+    })
+}
+
+/// Kinds are stored by name, like in the CSV file
+impl ToSql for Kind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for Kind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let name = value.as_str()?;
+        Kind::from_str(name, false).map_err(|e| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Splits are stored in the format of the --split option, like in the CSV file
+impl ToSql for Split {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for Split {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+fn insert_with_id(connection: &Connection, expense: &Expense) -> Result<(), rusqlite::Error> {
+    connection.execute(
+        &format!("INSERT INTO expenses ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"),
+        params![
+            expense.id, expense.date, expense.description, expense.amount.cents(),
+            expense.category, tag_list::join(&expense.tags), expense.currency, expense.reference, expense.kind,
+            expense.account, expense.to_account, expense.paid_by, expense.split,
+        ],
+    )?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Vec<Expense>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {COLUMNS} FROM expenses ORDER BY id"))?;
+        let expenses = statement.query_map([], from_row)?.collect::<Result<_, _>>()?;
+        Ok(expenses)
+    }
+
+    fn next_id(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(self.connection.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM expenses", [], |row| row.get(0))?)
+    }
+
+    fn insert(&mut self, mut expense: Expense) -> Result<u32, Box<dyn Error>> {
+        // Reading the next id and inserting happen in one transaction
+        let transaction = self.connection.transaction()?;
+        expense.id = transaction.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM expenses", [], |row| row.get(0))?;
+        insert_with_id(&transaction, &expense)?;
+        transaction.commit()?;
+        Ok(expense.id)
+    }
+
+    fn update(&mut self, expense: Expense) -> Result<bool, Box<dyn Error>> {
+        let changed = self.connection.execute(
+            "UPDATE expenses SET date = ?2, description = ?3, amount = ?4, category = ?5, tags = ?6, currency = ?7, reference = ?8, kind = ?9, account = ?10, to_account = ?11, paid_by = ?12, split = ?13 WHERE id = ?1",
+            params![
+                expense.id, expense.date, expense.description, expense.amount.cents(),
+                expense.category, tag_list::join(&expense.tags), expense.currency, expense.reference, expense.kind,
+                expense.account, expense.to_account, expense.paid_by, expense.split,
+            ],
+        )?;
+        Ok(changed > 0)
+    }
+
+    fn delete(&mut self, id: u32) -> Result<bool, Box<dyn Error>> {
+        let changed = self.connection.execute("DELETE FROM expenses WHERE id = ?1", [id])?;
+        Ok(changed > 0)
+    }
+
+    fn replace_all(&mut self, expenses: Vec<Expense>) -> Result<(), Box<dyn Error>> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM expenses", [])?;
+        for expense in &expenses {
+            insert_with_id(&transaction, expense)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get(&mut self, id: u32) -> Result<Option<Expense>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {COLUMNS} FROM expenses WHERE id = ?1"))?;
+        let expense = statement.query_map([id], from_row)?.next().transpose()?;
+        Ok(expense)
+    }
+
+    fn on_date(&mut self, date: NaiveDate) -> Result<Vec<Expense>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {COLUMNS} FROM expenses WHERE date = ?1 ORDER BY id"))?;
+        let expenses = statement.query_map([date], from_row)?.collect::<Result<_, _>>()?;
+        Ok(expenses)
+    }
+}